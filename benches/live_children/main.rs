@@ -0,0 +1,118 @@
+//! Compares the registry of live children with the sorted linked list it
+//! replaced. Run with `cargo bench`.
+
+#[allow(dead_code)]
+#[path = "../../src/registry.rs"]
+mod registry;
+mod sll;
+
+use std::{
+    cell::Cell,
+    rc::{Rc, Weak},
+    time::{Duration, Instant},
+};
+
+use crate::registry::Registry;
+
+struct Elem {
+    prev: Cell<Weak<Elem>>,
+    next: Cell<Weak<Elem>>,
+    key: Cell<usize>,
+}
+
+impl sll::Elem for Elem {
+    fn prev(&self) -> &Cell<Weak<Self>> {
+        &self.prev
+    }
+    fn next(&self) -> &Cell<Weak<Self>> {
+        &self.next
+    }
+    fn key(&self) -> &Cell<usize> {
+        &self.key
+    }
+}
+
+fn new_elem(key: usize) -> Rc<Elem> {
+    let elem =
+        Rc::new(Elem { prev: Default::default(), next: Default::default(), key: Cell::new(key) });
+    elem.prev.set(Rc::downgrade(&elem));
+    elem.next.set(Rc::downgrade(&elem));
+    elem
+}
+
+/// Times the steps of a workload over `n` live children of a single parent:
+/// registering handles to all children, inserting `n` more children in front
+/// of them, looking up the index of every handle and dropping the handles.
+fn bench_sll(n: usize) -> [Duration; 4] {
+    let head = Cell::new(Weak::new());
+    let start = Instant::now();
+    let elems: Vec<Rc<Elem>> = (0..n)
+        .map(|key| {
+            let mut elem = new_elem(key);
+            sll::link(&head, &mut elem);
+            elem
+        })
+        .collect();
+    let link = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..n {
+        sll::adjust(&elems[0], 0, 1);
+    }
+    let shift = start.elapsed();
+
+    let start = Instant::now();
+    let sum: usize = elems.iter().map(|it| it.key.get()).sum();
+    assert_eq!(sum, n * n + n * (n - 1) / 2);
+    let lookup = start.elapsed();
+
+    let start = Instant::now();
+    for elem in &elems {
+        sll::unlink(&head, elem);
+    }
+    let unlink = start.elapsed();
+    [link, shift, lookup, unlink]
+}
+
+fn bench_registry(n: usize) -> [Duration; 4] {
+    let mut registry = Registry::default();
+    let start = Instant::now();
+    let slots: Vec<usize> = (0..n).map(|key| registry.insert(key, ())).collect();
+    let link = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..n {
+        registry.shift(0, 1);
+    }
+    let shift = start.elapsed();
+
+    let start = Instant::now();
+    let sum: usize = slots.iter().map(|&it| registry.key(it)).sum();
+    assert_eq!(sum, n * n + n * (n - 1) / 2);
+    let lookup = start.elapsed();
+
+    let start = Instant::now();
+    for &slot in &slots {
+        registry.remove(slot);
+    }
+    let unlink = start.elapsed();
+    [link, shift, lookup, unlink]
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>10} {:>12} {:>12} {:>12} {:>12}",
+        "n", "", "link", "shift", "lookup", "unlink"
+    );
+    for &n in &[100, 1_000, 10_000, 30_000] {
+        for &(name, bench) in
+            &[("sll", bench_sll as fn(usize) -> [Duration; 4]), ("registry", bench_registry)]
+        {
+            let [link, shift, lookup, unlink] = bench(n);
+            println!(
+                "{:>8} {:>10} {:>12?} {:>12?} {:>12?} {:>12?}",
+                n, name, link, shift, lookup, unlink
+            );
+        }
+    }
+}
@@ -1,18 +1,20 @@
 //! Sorted Linked List
+//!
+//! The registry of live children before `Registry`, kept for comparison.
 
 use std::{
     cell::Cell,
     cmp::Ordering,
-    rc::{self, Rc},
+    rc::{Rc, Weak},
 };
 
 pub(crate) trait Elem {
-    fn prev(&self) -> &Cell<rc::Weak<Self>>;
-    fn next(&self) -> &Cell<rc::Weak<Self>>;
+    fn prev(&self) -> &Cell<Weak<Self>>;
+    fn next(&self) -> &Cell<Weak<Self>>;
     fn key(&self) -> &Cell<usize>;
 }
 
-pub(crate) fn unlink<E: Elem>(head: &Cell<rc::Weak<E>>, elem: &Rc<E>) {
+pub(crate) fn unlink<E: Elem>(head: &Cell<Weak<E>>, elem: &Rc<E>) {
     let prev = elem.prev().replace(Rc::downgrade(elem));
     let next = elem.next().replace(Rc::downgrade(elem));
 
@@ -20,9 +22,9 @@ pub(crate) fn unlink<E: Elem>(head: &Cell<rc::Weak<E>>, elem: &Rc<E>) {
 
     let old_head = head.take();
     head.set({
-        let i_am_head = old_head.as_ptr() == Rc::as_ptr(elem) as *const _;
+        let i_am_head = std::ptr::eq(old_head.as_ptr(), Rc::as_ptr(elem));
         match (i_am_head, single) {
-            (true, true) => rc::Weak::new(),
+            (true, true) => Weak::new(),
             (true, false) => next.clone(),
             (false, _) => old_head,
         }
@@ -36,25 +38,20 @@ pub(crate) fn unlink<E: Elem>(head: &Cell<rc::Weak<E>>, elem: &Rc<E>) {
     }
 }
 
-pub(crate) fn link<E: Elem>(head: &Cell<rc::Weak<E>>, elem: &mut Rc<E>) {
+pub(crate) fn link<E: Elem>(head: &Cell<Weak<E>>, elem: &mut Rc<E>) {
     let old_head = head.take();
     head.set(match old_head.upgrade() {
         Some(head) => {
             let mut curr = head.clone();
             let mut looped = false;
             loop {
-                match elem.key().cmp(&curr.key()) {
+                match elem.key().get().cmp(&curr.key().get()) {
                     Ordering::Equal => {
                         *elem = curr;
                         break;
                     }
                     Ordering::Greater if !looped => {
-                        curr = {
-                            let weak = curr.next().take();
-                            let rc = weak.upgrade().unwrap();
-                            curr.next().set(weak);
-                            rc
-                        };
+                        curr = cloned(curr.next()).upgrade().unwrap();
                         looped |= Rc::ptr_eq(&curr, &head);
                     }
                     Ordering::Less | Ordering::Greater => {
@@ -66,7 +63,7 @@ pub(crate) fn link<E: Elem>(head: &Cell<rc::Weak<E>>, elem: &mut Rc<E>) {
                     }
                 }
             }
-            Rc::downgrade(if elem.key() < head.key() { &*elem } else { &head })
+            Rc::downgrade(if elem.key().get() < head.key().get() { &*elem } else { &head })
         }
         None => Rc::downgrade(&*elem),
     })
@@ -79,14 +76,15 @@ pub(crate) fn adjust<E: Elem>(elem: &Rc<E>, from: usize, by: isize) {
         if key >= from {
             curr.key().set(((key as isize) + by) as usize);
         }
-        curr = {
-            let weak = curr.next().take();
-            let rc = weak.upgrade().unwrap();
-            curr.next().set(weak);
-            rc
-        };
+        curr = cloned(curr.next()).upgrade().unwrap();
         if Rc::ptr_eq(&curr, elem) {
             break;
         }
     }
 }
+
+fn cloned<T: Clone + Default>(cell: &Cell<T>) -> T {
+    let value = cell.take();
+    cell.set(value.clone());
+    value
+}
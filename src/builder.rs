@@ -0,0 +1,127 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::{NodeCache, PureChildKind, PureTree, RawKind};
+
+/// A point in the builder's output, which can later be wrapped into a tree
+/// with [`PureTreeBuilder::start_node_at`].
+#[derive(Clone, Copy, Debug)]
+pub struct Checkpoint(usize);
+
+#[derive(Debug)]
+enum MaybeOwned<'a, T> {
+    Owned(T),
+    Borrowed(&'a mut T),
+}
+
+impl<T> Deref for MaybeOwned<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        match self {
+            MaybeOwned::Owned(it) => it,
+            MaybeOwned::Borrowed(it) => it,
+        }
+    }
+}
+
+impl<T> DerefMut for MaybeOwned<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        match self {
+            MaybeOwned::Owned(it) => it,
+            MaybeOwned::Borrowed(it) => it,
+        }
+    }
+}
+
+/// Builds a `PureTree` top-down, from a stream of events, as produced by a
+/// recursive descent parser.
+///
+/// Tokens and trees are interned through a [`NodeCache`], which can be
+/// shared between builds to share elements across files and versions.
+#[derive(Debug)]
+pub struct PureTreeBuilder<'cache> {
+    cache: MaybeOwned<'cache, NodeCache>,
+    parents: Vec<(RawKind, usize)>,
+    children: Vec<PureChildKind>,
+}
+
+impl Default for PureTreeBuilder<'_> {
+    fn default() -> Self {
+        PureTreeBuilder {
+            cache: MaybeOwned::Owned(NodeCache::default()),
+            parents: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+}
+
+impl PureTreeBuilder<'static> {
+    pub fn new() -> PureTreeBuilder<'static> {
+        PureTreeBuilder::default()
+    }
+}
+
+impl<'cache> PureTreeBuilder<'cache> {
+    pub fn with_cache(cache: &'cache mut NodeCache) -> PureTreeBuilder<'cache> {
+        PureTreeBuilder {
+            cache: MaybeOwned::Borrowed(cache),
+            parents: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Starts a new tree and makes it current.
+    pub fn start_node(&mut self, kind: impl Into<RawKind>) {
+        let len = self.children.len();
+        self.parents.push((kind.into(), len));
+    }
+
+    /// Adds a token to the current tree.
+    pub fn token(&mut self, kind: impl Into<RawKind>, text: &str) {
+        let token = self.cache.token(kind, text);
+        self.children.push(token.into());
+    }
+
+    /// Finishes the current tree and restores the previous tree as current.
+    pub fn finish_node(&mut self) {
+        let (kind, first_child) = self.parents.pop().expect("finish_node without start_node");
+        let tree = self.cache.tree(kind, self.children.drain(first_child..));
+        self.children.push(tree.into());
+    }
+
+    /// Prepares for wrapping the elements added after this point into a
+    /// tree, which is useful for left-recursive constructs like `a + b`,
+    /// where the kind is known only after the first operand is parsed.
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        Checkpoint(self.children.len())
+    }
+
+    /// Wraps the elements added since `checkpoint` into a new tree, and
+    /// makes it current.
+    pub fn start_node_at(&mut self, checkpoint: Checkpoint, kind: impl Into<RawKind>) {
+        let Checkpoint(checkpoint) = checkpoint;
+        assert!(
+            checkpoint <= self.children.len(),
+            "checkpoint no longer valid, was finish_node called early?"
+        );
+        if let Some(&(_, first_child)) = self.parents.last() {
+            assert!(
+                checkpoint >= first_child,
+                "checkpoint no longer valid, was an unmatched start_node_at called?"
+            );
+        }
+        self.parents.push((kind.into(), checkpoint));
+    }
+
+    /// Completes building, returning the root tree.
+    ///
+    /// Panics unless every `start_node` was matched by `finish_node` and
+    /// exactly one root tree was built.
+    pub fn finish(mut self) -> PureTree {
+        assert!(self.parents.is_empty(), "unfinished trees: {}", self.parents.len());
+        assert_eq!(self.children.len(), 1, "expected a single root");
+        match self.children.pop().unwrap() {
+            PureChildKind::Tree(it) => it,
+            PureChildKind::Token(_) => panic!("expected a tree root, got a token"),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use crate::{PureChildKind, PureToken, PureTree, RawKind};
+
+/// Trees with more children than this are not interned: they are unlikely
+/// to repeat, and hashing them is costly.
+const MAX_CHILDREN: usize = 3;
+
+/// Interns tokens and small trees, so that structurally equal elements
+/// share the same allocation.
+///
+/// Trees are keyed by their kind and the identity of their children, so
+/// trees are deduplicated only if their children are interned as well. This
+/// is naturally the case for trees built bottom-up through the cache.
+#[derive(Default, Debug)]
+pub struct NodeCache {
+    tokens: HashMap<RawKind, HashMap<Box<str>, PureToken>>,
+    trees: HashMap<TreeKey, PureTree>,
+    stats: CacheStats,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub token_hits: usize,
+    pub token_misses: usize,
+    pub tree_hits: usize,
+    pub tree_misses: usize,
+}
+
+impl CacheStats {
+    pub fn token_hit_rate(&self) -> f64 {
+        hit_rate(self.token_hits, self.token_misses)
+    }
+    pub fn tree_hit_rate(&self) -> f64 {
+        hit_rate(self.tree_hits, self.tree_misses)
+    }
+}
+
+fn hit_rate(hits: usize, misses: usize) -> f64 {
+    if hits + misses == 0 {
+        return 0.0;
+    }
+    hits as f64 / (hits + misses) as f64
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct TreeKey {
+    kind: RawKind,
+    children: Vec<ChildId>,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum ChildId {
+    Tree(usize),
+    Token(usize),
+}
+
+impl ChildId {
+    fn of(child: &PureChildKind) -> ChildId {
+        match child {
+            PureChildKind::Tree(it) => ChildId::Tree(it.id()),
+            PureChildKind::Token(it) => ChildId::Token(it.id()),
+        }
+    }
+}
+
+impl NodeCache {
+    pub fn new() -> NodeCache {
+        NodeCache::default()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn token(&mut self, kind: impl Into<RawKind>, text: &str) -> PureToken {
+        let kind = kind.into();
+        let by_text = self.tokens.entry(kind).or_default();
+        if let Some(it) = by_text.get(text) {
+            self.stats.token_hits += 1;
+            return it.clone();
+        }
+        self.stats.token_misses += 1;
+        let token = PureToken::new(kind, text);
+        by_text.insert(text.into(), token.clone());
+        token
+    }
+
+    pub fn tree(
+        &mut self,
+        kind: impl Into<RawKind>,
+        children: impl IntoIterator<Item = PureChildKind>,
+    ) -> PureTree {
+        let mut tree = PureTree::new(kind);
+        for child in children {
+            tree = tree.push(child);
+        }
+        self.intern_tree(tree.into())
+    }
+
+    pub fn intern_token(&mut self, token: PureToken) -> PureToken {
+        let by_text = self.tokens.entry(token.kind()).or_default();
+        if let Some(it) = by_text.get(token.text()) {
+            self.stats.token_hits += 1;
+            return it.clone();
+        }
+        self.stats.token_misses += 1;
+        by_text.insert(token.text().into(), token.clone());
+        token
+    }
+
+    /// Interns `tree` itself, but not its children.
+    pub fn intern_tree(&mut self, tree: PureTree) -> PureTree {
+        if tree.children_count() > MAX_CHILDREN {
+            return tree;
+        }
+        let key = TreeKey {
+            kind: tree.kind(),
+            children: tree.children().map(|it| ChildId::of(&it.kind)).collect(),
+        };
+        match self.trees.entry(key) {
+            Entry::Occupied(entry) => {
+                self.stats.tree_hits += 1;
+                entry.get().clone()
+            }
+            Entry::Vacant(entry) => {
+                self.stats.tree_misses += 1;
+                entry.insert(tree.clone());
+                tree
+            }
+        }
+    }
+
+    pub fn intern_child(&mut self, child: PureChildKind) -> PureChildKind {
+        match child {
+            PureChildKind::Tree(it) => self.intern_tree(it).into(),
+            PureChildKind::Token(it) => self.intern_token(it).into(),
+        }
+    }
+
+    /// Like [`PureTree::insert_child`], but interns `child` and the result.
+    pub fn insert_child(
+        &mut self,
+        tree: &PureTree,
+        index: usize,
+        child: PureChildKind,
+    ) -> PureTree {
+        let child = self.intern_child(child);
+        let res = tree.insert_child(index, child);
+        self.intern_tree(res)
+    }
+
+    /// Like [`PureTree::replace_child`], but interns `child` and the result.
+    pub fn replace_child(
+        &mut self,
+        tree: &PureTree,
+        index: usize,
+        child: PureChildKind,
+    ) -> PureTree {
+        let child = self.intern_child(child);
+        let res = tree.replace_child(index, child);
+        self.intern_tree(res)
+    }
+}
@@ -0,0 +1,622 @@
+//! Storage for the children of a `PureTree`.
+//!
+//! Most trees have a handful of children, which are kept in a `Vec` together
+//! with their offsets. Trees with many children switch to a persistent
+//! B-tree whose nodes store the number of children and the text length
+//! below them, so that lookups by index or offset and edits are logarithmic,
+//! and edits copy only the path to the changed leaf.
+
+use std::{
+    hash::{Hash, Hasher},
+    mem,
+    ops::Range,
+    slice,
+    sync::Arc,
+};
+
+use crate::{delta::Delta, validate::Report, PureChild, PureChildKind, TextSize};
+
+/// Vectors longer than this become B-trees. B-trees shorter than half of
+/// this become vectors again.
+const MAX_SMALL: usize = 64;
+/// Maximum number of entries in a B-tree node.
+const MAX_NODE: usize = 32;
+/// Nodes with fewer entries are merged with a sibling.
+const MIN_NODE: usize = MAX_NODE / 4;
+
+#[derive(Clone)]
+pub(crate) enum Children {
+    Small(Vec<PureChild>),
+    Wide(Node),
+}
+
+#[derive(Clone)]
+pub(crate) struct Node {
+    count: usize,
+    text_len: TextSize,
+    entries: Arc<Entries>,
+}
+
+#[derive(Clone)]
+enum Entries {
+    Leaf(Vec<PureChildKind>),
+    Internal(Vec<Node>),
+}
+
+impl Children {
+    pub(crate) fn new() -> Children {
+        Children::Small(Vec::new())
+    }
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Children::Small(it) => it.len(),
+            Children::Wide(it) => it.count,
+        }
+    }
+    pub(crate) fn text_len(&self) -> TextSize {
+        match self {
+            Children::Small(it) => end(it),
+            Children::Wide(it) => it.text_len,
+        }
+    }
+    pub(crate) fn get(&self, index: usize) -> Option<PureChild> {
+        match self {
+            Children::Small(it) => it.get(index).cloned(),
+            Children::Wide(it) => it.iter_from(index).next(),
+        }
+    }
+    pub(crate) fn iter_from(&self, index: usize) -> Iter<'_> {
+        match self {
+            Children::Small(it) => Iter::Small(it.get(index..).unwrap_or_default().iter()),
+            Children::Wide(it) => it.iter_from(index),
+        }
+    }
+    /// The number of leading children whose end satisfies `pred`, which must
+    /// hold for a prefix of the children.
+    pub(crate) fn partition_by_end(&self, pred: impl Fn(TextSize) -> bool) -> usize {
+        match self {
+            Children::Small(it) => it.partition_point(|it| pred(it.text_range().end())),
+            Children::Wide(it) => it.partition_by_end(pred),
+        }
+    }
+
+    /// Checks if both are the same B-tree, which makes them equal.
+    pub(crate) fn shares_storage(&self, other: &Children) -> bool {
+        match (self, other) {
+            (Children::Wide(it), Children::Wide(other)) => Arc::ptr_eq(&it.entries, &other.entries),
+            _ => false,
+        }
+    }
+    /// Leaves no children, moving to `out` those which are not shared with
+    /// other trees.
+    pub(crate) fn take_owned(&mut self, out: &mut Vec<PureChildKind>) {
+        match mem::replace(self, Children::new()) {
+            Children::Small(children) => out.extend(children.into_iter().map(|it| it.kind)),
+            Children::Wide(root) => root.take_owned(out),
+        }
+    }
+
+    pub(crate) fn push(&mut self, child: PureChildKind) {
+        self.insert(self.len(), child)
+    }
+    pub(crate) fn insert(&mut self, index: usize, child: PureChildKind) {
+        match self {
+            Children::Small(children) => {
+                let offset = children.get(index).map_or_else(|| end(children), |it| it.offset);
+                let len = child.text_len();
+                children.insert(index, PureChild { offset, kind: child });
+                shift(&mut children[index + 1..], Delta::Add(len));
+            }
+            Children::Wide(root) => {
+                if let Some(right) = root.insert(index, child) {
+                    let left = mem::replace(root, Node::new(Entries::Internal(Vec::new())));
+                    *root = Node::new(Entries::Internal(vec![left, right]));
+                }
+            }
+        }
+        self.normalize()
+    }
+    pub(crate) fn remove(&mut self, index: usize) -> PureChildKind {
+        let removed = match self {
+            Children::Small(children) => {
+                let removed = children.remove(index).kind;
+                shift(&mut children[index..], Delta::Sub(removed.text_len()));
+                removed
+            }
+            Children::Wide(root) => {
+                let removed = root.remove(index);
+                while let Entries::Internal(nodes) = &*root.entries {
+                    if nodes.len() != 1 {
+                        break;
+                    }
+                    let only = nodes[0].clone();
+                    *root = only;
+                }
+                removed
+            }
+        };
+        self.normalize();
+        removed
+    }
+    pub(crate) fn replace(&mut self, index: usize, child: PureChildKind) -> PureChildKind {
+        match self {
+            Children::Small(children) => {
+                let delta = Delta::new(children[index].kind.text_len(), child.text_len());
+                let old = mem::replace(&mut children[index].kind, child);
+                shift(&mut children[index + 1..], delta);
+                old
+            }
+            Children::Wide(root) => root.replace(index, child),
+        }
+    }
+    pub(crate) fn splice(
+        &mut self,
+        range: Range<usize>,
+        children: impl Iterator<Item = PureChildKind>,
+    ) {
+        match self {
+            Children::Small(old) => {
+                let start = range.start;
+                let new = children.map(|kind| PureChild { offset: TextSize::default(), kind });
+                old.splice(range, new);
+                let mut offset = match start.checked_sub(1) {
+                    Some(prev) => old[prev].text_range().end(),
+                    None => TextSize::default(),
+                };
+                for child in &mut old[start..] {
+                    child.offset = offset;
+                    offset += child.kind.text_len();
+                }
+                self.normalize()
+            }
+            Children::Wide(_) => {
+                for _ in range.clone() {
+                    self.remove(range.start);
+                }
+                for (idx, child) in children.enumerate() {
+                    self.insert(range.start + idx, child);
+                }
+            }
+        }
+    }
+
+    /// Checks the storage and the offsets, but not the children themselves.
+    pub(crate) fn validate(&self, path: &[usize], report: &mut Report) {
+        match self {
+            Children::Small(children) => {
+                if children.len() > MAX_SMALL {
+                    let n = children.len();
+                    report.error(path, format!("{} children in a vector, max is {}", n, MAX_SMALL));
+                }
+                let mut offset = TextSize::default();
+                for (idx, child) in children.iter().enumerate() {
+                    if child.offset != offset {
+                        let message =
+                            format!("child {} at {:?}, expected {:?}", idx, child.offset, offset);
+                        report.error(path, message);
+                    }
+                    offset += child.kind.text_len();
+                }
+            }
+            Children::Wide(root) => {
+                if root.count < MAX_SMALL / 2 {
+                    let min = MAX_SMALL / 2;
+                    report.error(
+                        path,
+                        format!("{} children in a B-tree, min is {}", root.count, min),
+                    );
+                }
+                root.validate(path, report);
+            }
+        }
+    }
+
+    /// Switches between the vector and the B-tree representation.
+    fn normalize(&mut self) {
+        *self = match &*self {
+            Children::Small(it) if it.len() > MAX_SMALL => {
+                Children::Wide(Node::build(it.iter().map(|it| it.kind.clone()).collect()))
+            }
+            Children::Wide(it) if it.count < MAX_SMALL / 2 => {
+                Children::Small(it.iter_from(0).collect())
+            }
+            _ => return,
+        };
+    }
+}
+
+fn end(children: &[PureChild]) -> TextSize {
+    children.last().map_or(TextSize::default(), |it| it.text_range().end())
+}
+
+fn shift(children: &mut [PureChild], delta: Delta<TextSize>) {
+    for child in children {
+        child.offset += delta;
+    }
+}
+
+impl PartialEq for Children {
+    fn eq(&self, other: &Children) -> bool {
+        if self.shares_storage(other) {
+            return true;
+        }
+        self.len() == other.len()
+            && self.iter_from(0).zip(other.iter_from(0)).all(|(it, other)| it.kind == other.kind)
+    }
+}
+
+impl Eq for Children {}
+
+impl Hash for Children {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for child in self.iter_from(0) {
+            child.kind.hash(state);
+        }
+    }
+}
+
+impl Node {
+    fn new(entries: Entries) -> Node {
+        let (count, text_len) = match &entries {
+            Entries::Leaf(children) => {
+                (children.len(), children.iter().map(|it| it.text_len()).sum())
+            }
+            Entries::Internal(nodes) => {
+                (nodes.iter().map(|it| it.count).sum(), nodes.iter().map(|it| it.text_len).sum())
+            }
+        };
+        Node { count, text_len, entries: Arc::new(entries) }
+    }
+    /// Builds a balanced tree with full nodes.
+    fn build(children: Vec<PureChildKind>) -> Node {
+        let mut level: Vec<Node> =
+            chunks(children).into_iter().map(Entries::Leaf).map(Node::new).collect();
+        while level.len() > 1 {
+            level = chunks(level).into_iter().map(Entries::Internal).map(Node::new).collect();
+        }
+        level.pop().unwrap_or_else(|| Node::new(Entries::Leaf(Vec::new())))
+    }
+    /// The number of entries in this node, as opposed to `count`.
+    fn width(&self) -> usize {
+        match &*self.entries {
+            Entries::Leaf(it) => it.len(),
+            Entries::Internal(it) => it.len(),
+        }
+    }
+
+    fn iter_from(&self, mut index: usize) -> Iter<'_> {
+        let mut stack = Vec::new();
+        let mut offset = TextSize::default();
+        if index >= self.count {
+            return Iter::Wide { stack, leaf: [].iter(), offset };
+        }
+        let mut node = self;
+        loop {
+            match &*node.entries {
+                Entries::Leaf(children) => {
+                    offset += children[..index].iter().map(|it| it.text_len()).sum::<TextSize>();
+                    return Iter::Wide { stack, leaf: children[index..].iter(), offset };
+                }
+                Entries::Internal(nodes) => {
+                    let (i, rest) = locate(nodes, index);
+                    offset += nodes[..i].iter().map(|it| it.text_len).sum::<TextSize>();
+                    stack.push(nodes[i + 1..].iter());
+                    node = &nodes[i];
+                    index = rest;
+                }
+            }
+        }
+    }
+    fn partition_by_end(&self, pred: impl Fn(TextSize) -> bool) -> usize {
+        let mut node = self;
+        let mut count = 0;
+        let mut offset = TextSize::default();
+        loop {
+            match &*node.entries {
+                Entries::Leaf(children) => {
+                    for child in children {
+                        offset += child.text_len();
+                        if !pred(offset) {
+                            break;
+                        }
+                        count += 1;
+                    }
+                    return count;
+                }
+                Entries::Internal(nodes) => {
+                    let mut next = None;
+                    for sub in nodes {
+                        if !pred(offset + sub.text_len) {
+                            next = Some(sub);
+                            break;
+                        }
+                        count += sub.count;
+                        offset += sub.text_len;
+                    }
+                    match next {
+                        Some(sub) => node = sub,
+                        None => return count,
+                    }
+                }
+            }
+        }
+    }
+
+    fn take_owned(self, out: &mut Vec<PureChildKind>) {
+        match Arc::try_unwrap(self.entries) {
+            Ok(Entries::Leaf(children)) => out.extend(children),
+            Ok(Entries::Internal(nodes)) => nodes.into_iter().for_each(|it| it.take_owned(out)),
+            Err(_) => (),
+        }
+    }
+
+    /// Checks the summaries and the shape of the subtree, returning its
+    /// height.
+    fn validate(&self, path: &[usize], report: &mut Report) -> usize {
+        let (count, text_len, height) = match &*self.entries {
+            Entries::Leaf(children) => {
+                (children.len(), children.iter().map(|it| it.text_len()).sum(), 0)
+            }
+            Entries::Internal(nodes) => {
+                let heights: Vec<usize> =
+                    nodes.iter().map(|it| it.validate(path, report)).collect();
+                if heights.windows(2).any(|it| it[0] != it[1]) {
+                    report.error(path, "B-tree leaves at different depths");
+                }
+                if nodes.is_empty() {
+                    report.error(path, "empty internal B-tree node");
+                }
+                let count = nodes.iter().map(|it| it.count).sum();
+                let text_len = nodes.iter().map(|it| it.text_len).sum();
+                (count, text_len, heights.first().map_or(0, |it| it + 1))
+            }
+        };
+        if self.width() > MAX_NODE {
+            report.error(path, format!("B-tree node of {}, max is {}", self.width(), MAX_NODE));
+        }
+        if self.count != count {
+            report.error(path, format!("B-tree node counts {} children of {}", self.count, count));
+        }
+        if self.text_len != text_len {
+            let message =
+                format!("B-tree node has length {:?}, expected {:?}", self.text_len, text_len);
+            report.error(path, message);
+        }
+        height
+    }
+
+    /// Inserts `child`, returning the right half of this node if it had to
+    /// be split.
+    fn insert(&mut self, index: usize, child: PureChildKind) -> Option<Node> {
+        self.count += 1;
+        self.text_len += child.text_len();
+        let right = match Arc::make_mut(&mut self.entries) {
+            Entries::Leaf(children) => {
+                children.insert(index, child);
+                split(children).map(Entries::Leaf)
+            }
+            Entries::Internal(nodes) => {
+                let (i, index) = locate(nodes, index);
+                if let Some(right) = nodes[i].insert(index, child) {
+                    nodes.insert(i + 1, right);
+                }
+                split(nodes).map(Entries::Internal)
+            }
+        };
+        let right = Node::new(right?);
+        self.count -= right.count;
+        self.text_len -= right.text_len;
+        Some(right)
+    }
+    fn remove(&mut self, index: usize) -> PureChildKind {
+        let removed = match Arc::make_mut(&mut self.entries) {
+            Entries::Leaf(children) => children.remove(index),
+            Entries::Internal(nodes) => {
+                let (i, index) = locate(nodes, index);
+                let removed = nodes[i].remove(index);
+                if nodes[i].width() < MIN_NODE && nodes.len() > 1 {
+                    merge_with_sibling(nodes, i);
+                }
+                removed
+            }
+        };
+        self.count -= 1;
+        self.text_len -= removed.text_len();
+        removed
+    }
+    fn replace(&mut self, index: usize, child: PureChildKind) -> PureChildKind {
+        self.text_len += child.text_len();
+        let old = match Arc::make_mut(&mut self.entries) {
+            Entries::Leaf(children) => mem::replace(&mut children[index], child),
+            Entries::Internal(nodes) => {
+                let (i, index) = locate(nodes, index);
+                nodes[i].replace(index, child)
+            }
+        };
+        self.text_len -= old.text_len();
+        old
+    }
+}
+
+/// The node containing the child at `index`, and the index within that
+/// node. An index past the end falls into the last node.
+fn locate(nodes: &[Node], mut index: usize) -> (usize, usize) {
+    for (i, node) in nodes.iter().enumerate() {
+        if index < node.count || i + 1 == nodes.len() {
+            return (i, index);
+        }
+        index -= node.count;
+    }
+    unreachable!("empty internal node")
+}
+
+/// Splits off the right half of an overfull node.
+fn split<T>(entries: &mut Vec<T>) -> Option<Vec<T>> {
+    if entries.len() > MAX_NODE {
+        Some(entries.split_off(entries.len() / 2))
+    } else {
+        None
+    }
+}
+
+/// Merges the underfull `nodes[i]` with a neighbor, splitting the result
+/// again if it is too big.
+fn merge_with_sibling(nodes: &mut Vec<Node>, i: usize) {
+    let left = if i + 1 < nodes.len() { i } else { i - 1 };
+    let right = nodes.remove(left + 1);
+    let mut entries = Entries::clone(&nodes[left].entries);
+    let overflow = match (&mut entries, &*right.entries) {
+        (Entries::Leaf(left), Entries::Leaf(right)) => {
+            left.extend(right.iter().cloned());
+            split(left).map(Entries::Leaf)
+        }
+        (Entries::Internal(left), Entries::Internal(right)) => {
+            left.extend(right.iter().cloned());
+            split(left).map(Entries::Internal)
+        }
+        _ => unreachable!("siblings at different depths"),
+    };
+    nodes[left] = Node::new(entries);
+    if let Some(overflow) = overflow {
+        nodes.insert(left + 1, Node::new(overflow));
+    }
+}
+
+fn chunks<T>(items: Vec<T>) -> Vec<Vec<T>> {
+    let mut res = Vec::new();
+    let mut items = items.into_iter().peekable();
+    while items.peek().is_some() {
+        res.push(items.by_ref().take(MAX_NODE).collect());
+    }
+    res
+}
+
+pub(crate) enum Iter<'a> {
+    Small(slice::Iter<'a, PureChild>),
+    Wide {
+        // Nodes still to visit, innermost last.
+        stack: Vec<slice::Iter<'a, Node>>,
+        leaf: slice::Iter<'a, PureChildKind>,
+        offset: TextSize,
+    },
+}
+
+impl Iterator for Iter<'_> {
+    type Item = PureChild;
+
+    fn next(&mut self) -> Option<PureChild> {
+        let (stack, leaf, offset) = match self {
+            Iter::Small(it) => return it.next().cloned(),
+            Iter::Wide { stack, leaf, offset } => (stack, leaf, offset),
+        };
+        loop {
+            if let Some(kind) = leaf.next() {
+                let child = PureChild { offset: *offset, kind: kind.clone() };
+                *offset += kind.text_len();
+                return Some(child);
+            }
+            loop {
+                let node = match stack.last_mut()?.next() {
+                    Some(it) => it,
+                    None => {
+                        stack.pop();
+                        continue;
+                    }
+                };
+                match &*node.entries {
+                    Entries::Leaf(children) => {
+                        *leaf = children.iter();
+                        break;
+                    }
+                    Entries::Internal(nodes) => stack.push(nodes.iter()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PureToken;
+
+    fn token(text: &str) -> PureChildKind {
+        PureChildKind::Token(PureToken::new("x", text))
+    }
+
+    fn wide(n: usize) -> Node {
+        match (0..n).fold(Children::new(), |mut acc, _| {
+            acc.push(token("ab"));
+            acc
+        }) {
+            Children::Wide(it) => it,
+            Children::Small(_) => unreachable!(),
+        }
+    }
+
+    fn errors(children: &Children) -> Vec<String> {
+        let mut report = Report::default();
+        children.validate(&[1, 2], &mut report);
+        report.finish().map_or_else(|it| it.errors().to_vec(), |()| Vec::new())
+    }
+
+    #[test]
+    fn validate_small() {
+        let mut children: Vec<PureChild> = (0..3)
+            .map(|idx| PureChild { offset: TextSize::from(2 * idx), kind: token("ab") })
+            .collect();
+        assert!(errors(&Children::Small(children.clone())).is_empty());
+
+        children[1].offset = TextSize::from(3);
+        assert_eq!(errors(&Children::Small(children)), vec!["at [1, 2]: child 1 at 3, expected 2"]);
+
+        let children = Node::build((0..65).map(|_| token("")).collect()).iter_from(0).collect();
+        assert_eq!(
+            errors(&Children::Small(children)),
+            vec!["at [1, 2]: 65 children in a vector, max is 64"]
+        );
+    }
+
+    #[test]
+    fn validate_wide() {
+        let node = wide(100);
+        assert!(errors(&Children::Wide(node.clone())).is_empty());
+
+        let mut corrupted = node.clone();
+        corrupted.count += 1;
+        corrupted.text_len += TextSize::from(1);
+        assert_eq!(
+            errors(&Children::Wide(corrupted)),
+            vec![
+                "at [1, 2]: B-tree node counts 101 children of 100",
+                "at [1, 2]: B-tree node has length 201, expected 200",
+            ]
+        );
+
+        let small = Node::build((0..10).map(|_| token("ab")).collect());
+        assert_eq!(
+            errors(&Children::Wide(small)),
+            vec!["at [1, 2]: 10 children in a B-tree, min is 32"]
+        );
+
+        let leaf = Node::new(Entries::Leaf((0..40).map(|_| token("ab")).collect()));
+        let unbalanced = Node::new(Entries::Internal(vec![node, leaf]));
+        assert_eq!(
+            errors(&Children::Wide(unbalanced)),
+            vec![
+                "at [1, 2]: B-tree node of 40, max is 32",
+                "at [1, 2]: B-tree leaves at different depths",
+            ]
+        );
+
+        let empty = Node::new(Entries::Internal(Vec::new()));
+        assert_eq!(
+            errors(&Children::Wide(Node::new(Entries::Internal(vec![wide(100), empty])))),
+            vec![
+                "at [1, 2]: empty internal B-tree node",
+                "at [1, 2]: B-tree leaves at different depths",
+            ]
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use std::{fmt, iter, sync::Arc};
+
+use crate::{
+    Direction, PureChildKind, PureToken, PureTree, RawKind, TextRange, TextSize, TokenAtOffset,
+    WalkEvent,
+};
+
+/// A read-only position in a `PureTree`: an element together with its
+/// parent, index and absolute offset.
+///
+/// Unlike `SyntaxTree`, cursors are plain values: nothing is registered when
+/// a cursor is created, and the only allocations are the shared parent
+/// pointers. Cursors are `Send + Sync`.
+#[derive(Clone)]
+pub struct PureCursor {
+    parent: Option<Arc<PureCursor>>,
+    index: usize,
+    offset: TextSize,
+    element: PureChildKind,
+}
+
+impl PureCursor {
+    pub fn new(root: PureTree) -> PureCursor {
+        PureCursor { parent: None, index: 0, offset: TextSize::default(), element: root.into() }
+    }
+
+    pub fn element(&self) -> &PureChildKind {
+        &self.element
+    }
+    pub fn as_tree(&self) -> Option<&PureTree> {
+        match &self.element {
+            PureChildKind::Tree(it) => Some(it),
+            PureChildKind::Token(_) => None,
+        }
+    }
+    pub fn as_token(&self) -> Option<&PureToken> {
+        match &self.element {
+            PureChildKind::Tree(_) => None,
+            PureChildKind::Token(it) => Some(it),
+        }
+    }
+    pub fn kind(&self) -> RawKind {
+        self.element.kind()
+    }
+    pub fn offset(&self) -> TextSize {
+        self.offset
+    }
+    pub fn text_len(&self) -> TextSize {
+        self.element.text_len()
+    }
+    pub fn text_range(&self) -> TextRange {
+        TextRange::at(self.offset, self.text_len())
+    }
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn parent(&self) -> Option<PureCursor> {
+        self.parent.as_deref().cloned()
+    }
+    pub fn ancestors(&self) -> impl Iterator<Item = PureCursor> {
+        iter::successors(self.parent(), PureCursor::parent)
+    }
+    pub fn children_count(&self) -> usize {
+        self.as_tree().map_or(0, PureTree::children_count)
+    }
+    pub fn get_child(&self, index: usize) -> Option<PureCursor> {
+        PureCursor::child_of(&Arc::new(self.clone()), index)
+    }
+    pub fn children(&self) -> impl Iterator<Item = PureCursor> {
+        let parent = Arc::new(self.clone());
+        (0..self.children_count()).filter_map(move |idx| PureCursor::child_of(&parent, idx))
+    }
+    pub fn first_child(&self) -> Option<PureCursor> {
+        self.get_child(0)
+    }
+    pub fn last_child(&self) -> Option<PureCursor> {
+        self.get_child(self.children_count().checked_sub(1)?)
+    }
+    pub fn next_sibling(&self) -> Option<PureCursor> {
+        PureCursor::child_of(self.parent.as_ref()?, self.index + 1)
+    }
+    pub fn prev_sibling(&self) -> Option<PureCursor> {
+        PureCursor::child_of(self.parent.as_ref()?, self.index.checked_sub(1)?)
+    }
+    /// This element and its siblings in the given direction.
+    pub fn siblings(&self, direction: Direction) -> impl Iterator<Item = PureCursor> {
+        iter::successors(Some(self.clone()), move |it| it.sibling(direction))
+    }
+
+    /// The first token of this element, which is the element itself for
+    /// tokens.
+    pub fn first_token(&self) -> Option<PureCursor> {
+        self.edge_token(Direction::Next)
+    }
+    /// The last token of this element, which is the element itself for
+    /// tokens.
+    pub fn last_token(&self) -> Option<PureCursor> {
+        self.edge_token(Direction::Prev)
+    }
+    /// The first token after this element in document order.
+    pub fn next_token(&self) -> Option<PureCursor> {
+        self.neighbor_token(Direction::Next)
+    }
+    /// The last token before this element in document order.
+    pub fn prev_token(&self) -> Option<PureCursor> {
+        self.neighbor_token(Direction::Prev)
+    }
+
+    pub fn preorder_with_tokens(&self) -> PureCursorPreorder {
+        PureCursorPreorder { next: Some(WalkEvent::Enter(self.clone())), depth: 0 }
+    }
+    pub fn descendants(&self) -> impl Iterator<Item = PureCursor> {
+        self.descendants_with_tokens().filter(|it| it.as_tree().is_some())
+    }
+    pub fn descendants_with_tokens(&self) -> impl Iterator<Item = PureCursor> {
+        self.preorder_with_tokens().filter_map(|event| match event {
+            WalkEvent::Enter(it) => Some(it),
+            WalkEvent::Leave(_) => None,
+        })
+    }
+
+    /// Finds the token(s) at `offset`, which must be within the element's
+    /// range.
+    pub fn token_at_offset(&self, offset: TextSize) -> TokenAtOffset<PureCursor> {
+        let range = self.text_range();
+        assert!(range.contains_inclusive(offset), "bad offset: {:?} not in {:?}", offset, range);
+        let mut curr = self.clone();
+        loop {
+            let (left, right) = match curr.as_tree() {
+                None => return TokenAtOffset::Single(curr),
+                Some(tree) => {
+                    let mut touching = tree
+                        .children_touching(offset - curr.offset)
+                        .filter(|(_, it)| !it.text_range().is_empty())
+                        .map(|(idx, _)| idx);
+                    let left = touching.next();
+                    let right = touching.next();
+                    assert!(touching.next().is_none());
+                    (left, right)
+                }
+            };
+            let parent = Arc::new(curr);
+            curr = match (left, right) {
+                (None, _) => return TokenAtOffset::None,
+                (Some(left), Some(right)) => {
+                    let single = |idx| match PureCursor::child_of(&parent, idx)
+                        .unwrap()
+                        .token_at_offset(offset)
+                    {
+                        TokenAtOffset::Single(it) => it,
+                        _ => unreachable!(),
+                    };
+                    return TokenAtOffset::Between(single(left), single(right));
+                }
+                (Some(left), None) => PureCursor::child_of(&parent, left).unwrap(),
+            };
+        }
+    }
+    /// Finds the smallest element whose range contains `range`, which must
+    /// be within the element's range.
+    pub fn covering_element(&self, range: TextRange) -> PureCursor {
+        let self_range = self.text_range();
+        assert!(self_range.contains_range(range), "bad range: {:?} not in {:?}", range, self_range);
+        let mut curr = self.clone();
+        loop {
+            let idx = match curr.as_tree().and_then(|it| it.child_covering(range - curr.offset)) {
+                Some((idx, _)) => idx,
+                None => return curr,
+            };
+            curr = PureCursor::child_of(&Arc::new(curr), idx).unwrap();
+        }
+    }
+
+    fn child_of(parent: &Arc<PureCursor>, index: usize) -> Option<PureCursor> {
+        let child = parent.as_tree()?.get_child(index)?;
+        Some(PureCursor {
+            parent: Some(parent.clone()),
+            index,
+            offset: parent.offset + child.offset,
+            element: child.kind.clone(),
+        })
+    }
+    fn sibling(&self, direction: Direction) -> Option<PureCursor> {
+        match direction {
+            Direction::Next => self.next_sibling(),
+            Direction::Prev => self.prev_sibling(),
+        }
+    }
+    /// The sibling in `direction`, or the sibling of the closest ancestor
+    /// which has one, without leaving `stop`.
+    fn step_out(&self, direction: Direction, stop: Option<&Arc<PureCursor>>) -> Option<PureCursor> {
+        let mut curr = self.clone();
+        loop {
+            if let Some(it) = curr.sibling(direction) {
+                return Some(it);
+            }
+            let parent = curr.parent.take()?;
+            if stop.is_some_and(|stop| Arc::ptr_eq(stop, &parent)) {
+                return None;
+            }
+            curr = PureCursor::clone(&parent);
+        }
+    }
+    fn edge_token(&self, direction: Direction) -> Option<PureCursor> {
+        let edge_child = |tree: &Arc<PureCursor>| {
+            let index = match direction {
+                Direction::Next => 0,
+                Direction::Prev => tree.children_count().checked_sub(1)?,
+            };
+            PureCursor::child_of(tree, index)
+        };
+        if self.as_token().is_some() {
+            return Some(self.clone());
+        }
+        let root = Arc::new(self.clone());
+        let mut curr = edge_child(&root)?;
+        loop {
+            if curr.as_token().is_some() {
+                return Some(curr);
+            }
+            let tree = Arc::new(curr);
+            curr = match edge_child(&tree) {
+                Some(child) => child,
+                None => tree.step_out(direction, Some(&root))?,
+            };
+        }
+    }
+    fn neighbor_token(&self, direction: Direction) -> Option<PureCursor> {
+        let mut curr = self.step_out(direction, None)?;
+        loop {
+            if let Some(it) = curr.edge_token(direction) {
+                return Some(it);
+            }
+            curr = curr.step_out(direction, None)?;
+        }
+    }
+}
+
+// Cursors share their ancestors, which are compared and dropped in a loop to
+// support trees of any depth.
+impl PartialEq for PureCursor {
+    fn eq(&self, other: &PureCursor) -> bool {
+        let (mut curr, mut other) = (self, other);
+        loop {
+            if curr.index != other.index
+                || curr.offset != other.offset
+                || curr.element != other.element
+            {
+                return false;
+            }
+            match (&curr.parent, &other.parent) {
+                (Some(it), Some(other_parent)) if Arc::ptr_eq(it, other_parent) => return true,
+                (Some(it), Some(other_parent)) => {
+                    curr = it;
+                    other = other_parent;
+                }
+                (None, None) => return true,
+                _ => return false,
+            }
+        }
+    }
+}
+
+impl Eq for PureCursor {}
+
+impl Drop for PureCursor {
+    fn drop(&mut self) {
+        let mut parent = self.parent.take();
+        while let Some(it) = parent {
+            parent = match Arc::try_unwrap(it) {
+                Ok(mut it) => it.parent.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+impl fmt::Debug for PureCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}@{:?}", self.kind(), self.text_range())
+    }
+}
+
+/// Preorder traversal of trees and tokens below a cursor.
+pub struct PureCursorPreorder {
+    next: Option<WalkEvent<PureCursor>>,
+    // How deep `next` is below the starting element.
+    depth: usize,
+}
+
+impl PureCursorPreorder {
+    /// Skips the children of the tree which was just entered.
+    pub fn skip_subtree(&mut self) {
+        if let Some(WalkEvent::Enter(child)) = &self.next {
+            if self.depth > 0 {
+                self.depth -= 1;
+                self.next = child.parent().map(WalkEvent::Leave);
+            }
+        }
+    }
+}
+
+impl Iterator for PureCursorPreorder {
+    type Item = WalkEvent<PureCursor>;
+
+    fn next(&mut self) -> Option<WalkEvent<PureCursor>> {
+        let next = self.next.take()?;
+        self.next = match &next {
+            WalkEvent::Enter(it) => match it.first_child() {
+                Some(child) => {
+                    self.depth += 1;
+                    Some(WalkEvent::Enter(child))
+                }
+                None => Some(WalkEvent::Leave(it.clone())),
+            },
+            WalkEvent::Leave(_) if self.depth == 0 => None,
+            WalkEvent::Leave(it) => match it.next_sibling() {
+                Some(sibling) => Some(WalkEvent::Enter(sibling)),
+                None => {
+                    self.depth -= 1;
+                    it.parent().map(WalkEvent::Leave)
+                }
+            },
+        };
+        Some(next)
+    }
+}
@@ -1,5 +1,7 @@
 use std::ops::{AddAssign, Sub};
 
+use crate::TextSize;
+
 #[derive(Copy, Clone, Debug)]
 pub enum Delta<T> {
     Add(T),
@@ -18,8 +20,8 @@ impl<T: Ord + Sub<Output = T>> Delta<T> {
 
 // This won't be coherent :-(
 // impl<T: AddAssign + SubAssign> AddAssign<Delta<T>> for T
-impl AddAssign<Delta<usize>> for usize {
-    fn add_assign(&mut self, rhs: Delta<usize>) {
+impl AddAssign<Delta<TextSize>> for TextSize {
+    fn add_assign(&mut self, rhs: Delta<TextSize>) {
         match rhs {
             Delta::Add(amt) => *self += amt,
             Delta::Sub(amt) => *self -= amt,
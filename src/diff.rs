@@ -0,0 +1,186 @@
+use std::iter;
+
+use crate::{Language, PureChildKind, PureTree, SyntaxChild, SyntaxTree};
+
+/// An edit script turning one `PureTree` into another, computed by [`diff`].
+#[derive(Debug, Clone, Default)]
+pub struct TreeDiff {
+    ops: Vec<DiffOp>,
+}
+
+/// A single edit of the children of the tree at `path`.
+///
+/// `path` lists child indices from the root. Operations are meant to be
+/// applied in order: paths and indices refer to the tree as modified by all
+/// preceding operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffOp {
+    Insert { path: Vec<usize>, index: usize, child: PureChildKind },
+    Delete { path: Vec<usize>, index: usize },
+    Replace { path: Vec<usize>, index: usize, child: PureChildKind },
+}
+
+/// Computes the child-level edits turning `old` into `new`.
+///
+/// Subtrees shared between the two versions (as determined by pointer
+/// equality) are skipped without looking inside, and kept in place when
+/// their siblings change. Aligning the children that differ takes time
+/// quadratic in their number. Roots must be of the same kind.
+pub fn diff(old: &PureTree, new: &PureTree) -> TreeDiff {
+    assert_eq!(old.kind(), new.kind(), "can't diff roots of different kinds");
+    let mut ops = Vec::new();
+    // Steps still to do, innermost last, each with the length of the path to
+    // the tree it applies to.
+    let mut steps = Vec::new();
+    diff_children(0, old, new, &mut steps);
+    let mut path = Vec::new();
+    while let Some((depth, step)) = steps.pop() {
+        path.truncate(depth);
+        match step {
+            Step::Diff(index, old, new) => {
+                path.push(index);
+                diff_children(depth + 1, &old, &new, &mut steps);
+            }
+            Step::Insert(index, child) => {
+                ops.push(DiffOp::Insert { path: path.clone(), index, child })
+            }
+            Step::Delete(index) => ops.push(DiffOp::Delete { path: path.clone(), index }),
+            Step::Replace(index, child) => {
+                ops.push(DiffOp::Replace { path: path.clone(), index, child })
+            }
+        }
+    }
+    TreeDiff { ops }
+}
+
+/// Work left for `diff`: comparing a pair of children, or emitting an edit.
+enum Step {
+    Diff(usize, PureTree, PureTree),
+    Insert(usize, PureChildKind),
+    Delete(usize),
+    Replace(usize, PureChildKind),
+}
+
+/// Schedules the steps turning the children of `old` into those of `new`.
+fn diff_children(depth: usize, old: &PureTree, new: &PureTree, steps: &mut Vec<(usize, Step)>) {
+    if old.ptr_eq(new) {
+        return;
+    }
+    let old_children: Vec<PureChildKind> = old.children().map(|it| it.kind).collect();
+    let new_children: Vec<PureChildKind> = new.children().map(|it| it.kind).collect();
+
+    let prefix = old_children.iter().zip(&new_children).take_while(|(o, n)| same(o, n)).count();
+    let (old_rest, new_rest) = (&old_children[prefix..], &new_children[prefix..]);
+    let suffix =
+        old_rest.iter().rev().zip(new_rest.iter().rev()).take_while(|(o, n)| same(o, n)).count();
+    let old_mid = &old_rest[..old_rest.len() - suffix];
+    let new_mid = &new_rest[..new_rest.len() - suffix];
+
+    // Children kept in both versions stay where they are, so that their
+    // handles survive `apply`. Those in between are paired by position.
+    let mut res = Vec::new();
+    let mut index = prefix;
+    let (mut old_start, mut new_start) = (0, 0);
+    let ends = iter::once((old_mid.len(), new_mid.len()));
+    for (old_end, new_end) in common_children(old_mid, new_mid).into_iter().chain(ends) {
+        let (old_gap, new_gap) = (&old_mid[old_start..old_end], &new_mid[new_start..new_end]);
+        for (o, n) in old_gap.iter().zip(new_gap) {
+            match (o, n) {
+                (PureChildKind::Tree(o), PureChildKind::Tree(n)) if o.kind() == n.kind() => {
+                    res.push(Step::Diff(index, o.clone(), n.clone()))
+                }
+                _ => res.push(Step::Replace(index, n.clone())),
+            }
+            index += 1;
+        }
+        let common = old_gap.len().min(new_gap.len());
+        for _ in common..old_gap.len() {
+            res.push(Step::Delete(index));
+        }
+        for n in &new_gap[common..] {
+            res.push(Step::Insert(index, n.clone()));
+            index += 1;
+        }
+        // Skips the common child.
+        index += 1;
+        (old_start, new_start) = (old_end + 1, new_end + 1);
+    }
+    steps.extend(res.into_iter().rev().map(|step| (depth, step)));
+}
+
+/// Indices of a longest common subsequence of `old` and `new`, using `same`
+/// to compare children.
+fn common_children(old: &[PureChildKind], new: &[PureChildKind]) -> Vec<(usize, usize)> {
+    // `lens[i][j]` is the length of the subsequence for `old[i..]` and
+    // `new[j..]`.
+    let mut lens = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lens[i][j] = if same(&old[i], &new[j]) {
+                lens[i + 1][j + 1] + 1
+            } else {
+                lens[i + 1][j].max(lens[i][j + 1])
+            };
+        }
+    }
+    let mut res = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if same(&old[i], &new[j]) {
+            res.push((i, j));
+            (i, j) = (i + 1, j + 1);
+        } else if lens[i + 1][j] >= lens[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    res
+}
+
+fn same(old: &PureChildKind, new: &PureChildKind) -> bool {
+    match (old, new) {
+        (PureChildKind::Tree(o), PureChildKind::Tree(n)) => o.ptr_eq(n),
+        (PureChildKind::Token(o), PureChildKind::Token(n)) => o == n,
+        _ => false,
+    }
+}
+
+impl TreeDiff {
+    pub fn ops(&self) -> &[DiffOp] {
+        &self.ops
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Replays the diff onto a live tree, which must be in the `old` state.
+    ///
+    /// Only the changed elements are touched, so handles to unchanged
+    /// elements stay valid.
+    pub fn apply<L: Language>(&self, root: &SyntaxTree<L>) {
+        for op in &self.ops {
+            match op {
+                DiffOp::Insert { path, index, child } => {
+                    resolve(root, path).insert_child(*index, child.clone().into())
+                }
+                DiffOp::Delete { path, index } => {
+                    resolve(root, path).get_child(*index).unwrap().detach()
+                }
+                DiffOp::Replace { path, index, child } => resolve(root, path)
+                    .get_child(*index)
+                    .unwrap()
+                    .replace_with(child.clone().into()),
+            }
+        }
+    }
+}
+
+fn resolve<L: Language>(root: &SyntaxTree<L>, path: &[usize]) -> SyntaxTree<L> {
+    let mut tree = root.clone();
+    for &index in path {
+        tree = tree.get_child(index).and_then(SyntaxChild::into_tree).unwrap();
+    }
+    tree
+}
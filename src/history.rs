@@ -0,0 +1,98 @@
+use crate::{
+    recorder::Observer,
+    shared::{Cell, Rc, RefCell, Weak},
+    Language, PureTree, SyntaxTree, TextRange, TextSize,
+};
+
+/// Undo/redo stack of the states of a `SyntaxTree`.
+///
+/// Every mutation of the tree is captured as a snapshot of its `PureTree`,
+/// which is cheap as consecutive snapshots share most of their structure.
+/// Undoing or redoing a change resets the tree to a snapshot: live handles
+/// are kept if an element of the same kind exists at the same index path,
+/// and are detached otherwise.
+pub struct History<L: Language> {
+    tree: SyntaxTree<L>,
+    snapshots: Rc<Snapshots>,
+}
+
+struct Snapshots {
+    entries: RefCell<Vec<Snapshot>>,
+    current: Cell<usize>,
+    restoring: Cell<bool>,
+}
+
+struct Snapshot {
+    tree: PureTree,
+    label: Option<String>,
+}
+
+impl<L: Language> History<L> {
+    pub fn new(tree: &SyntaxTree<L>) -> History<L> {
+        let initial = Snapshot { tree: tree.snapshot(), label: None };
+        let snapshots = Rc::new(Snapshots {
+            entries: RefCell::new(vec![initial]),
+            current: Cell::new(0),
+            restoring: Cell::new(false),
+        });
+        let observer: Weak<dyn Observer> = Rc::downgrade(&snapshots) as Weak<Snapshots>;
+        tree.add_observer(observer);
+        History { tree: tree.clone(), snapshots }
+    }
+
+    /// Labels the current state, e.g. with the name of the refactoring that
+    /// produced it.
+    pub fn set_label(&self, label: impl Into<String>) {
+        let current = self.snapshots.current.get();
+        self.snapshots.entries.borrow_mut()[current].label = Some(label.into());
+    }
+    pub fn label(&self) -> Option<String> {
+        let current = self.snapshots.current.get();
+        self.snapshots.entries.borrow()[current].label.clone()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.snapshots.current.get() > 0
+    }
+    pub fn can_redo(&self) -> bool {
+        self.snapshots.current.get() + 1 < self.snapshots.entries.borrow().len()
+    }
+
+    /// Returns `false` if there's nothing to undo.
+    pub fn undo(&self) -> bool {
+        if !self.can_undo() {
+            return false;
+        }
+        self.restore(self.snapshots.current.get() - 1);
+        true
+    }
+    /// Returns `false` if there's nothing to redo.
+    pub fn redo(&self) -> bool {
+        if !self.can_redo() {
+            return false;
+        }
+        self.restore(self.snapshots.current.get() + 1);
+        true
+    }
+
+    fn restore(&self, index: usize) {
+        let tree = self.snapshots.entries.borrow()[index].tree.clone();
+        self.snapshots.current.set(index);
+        self.snapshots.restoring.set(true);
+        self.tree.reset_pure(tree);
+        self.snapshots.restoring.set(false);
+    }
+}
+
+impl Observer for Snapshots {
+    fn on_change(&self, _range: TextRange, _new_len: TextSize, new: &PureTree) {
+        if self.restoring.get() {
+            return;
+        }
+        let mut entries = self.entries.borrow_mut();
+        let current = self.current.get() + 1;
+        entries.truncate(current);
+        entries.push(Snapshot { tree: new.clone(), label: None });
+        self.current.set(current);
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt,
+    hash::Hash,
+    sync::{OnceLock, PoisonError, RwLock},
+};
+
+/// Untyped kind of a tree or a token, as stored in `PureTree` and `PureToken`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RawKind(pub u16);
+
+impl From<u16> for RawKind {
+    fn from(raw: u16) -> RawKind {
+        RawKind(raw)
+    }
+}
+
+/// Maps raw kinds to the typed kinds of a particular language.
+pub trait Language: Sized + Copy + fmt::Debug + Eq + Ord + Hash {
+    type Kind: Sized + Copy + fmt::Debug + Eq + Ord + Hash;
+
+    fn kind_from_raw(raw: RawKind) -> Self::Kind;
+    fn kind_to_raw(kind: Self::Kind) -> RawKind;
+
+    /// Like `kind_from_raw`, but returns `None` instead of panicking on raw
+    /// kinds which don't belong to this language. `Debug` impls print those
+    /// as `RawKind`s.
+    fn try_kind_from_raw(raw: RawKind) -> Option<Self::Kind> {
+        Some(Self::kind_from_raw(raw))
+    }
+}
+
+/// A language whose kinds are plain strings.
+///
+/// Strings are interned into a global table on first use, so raw kinds are
+/// only meaningful within a single process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StrLanguage {}
+
+impl Language for StrLanguage {
+    type Kind = &'static str;
+
+    fn kind_from_raw(raw: RawKind) -> &'static str {
+        match StrLanguage::try_kind_from_raw(raw) {
+            Some(it) => it,
+            None => panic!("{:?} was not interned by StrLanguage", raw),
+        }
+    }
+    fn try_kind_from_raw(raw: RawKind) -> Option<&'static str> {
+        let interner = interner().read().unwrap_or_else(PoisonError::into_inner);
+        interner.kinds.get(usize::from(raw.0)).copied()
+    }
+    fn kind_to_raw(kind: &'static str) -> RawKind {
+        let read = interner().read().unwrap_or_else(PoisonError::into_inner).ids.get(kind).copied();
+        if let Some(raw) = read {
+            return raw;
+        }
+        let mut interner = interner().write().unwrap_or_else(PoisonError::into_inner);
+        if let Some(&raw) = interner.ids.get(kind) {
+            return raw;
+        }
+        let raw = RawKind(u16::try_from(interner.kinds.len()).expect("too many kinds"));
+        interner.kinds.push(kind);
+        interner.ids.insert(kind, raw);
+        raw
+    }
+}
+
+impl From<&'static str> for RawKind {
+    fn from(kind: &'static str) -> RawKind {
+        StrLanguage::kind_to_raw(kind)
+    }
+}
+
+#[derive(Default)]
+struct Interner {
+    kinds: Vec<&'static str>,
+    ids: HashMap<&'static str, RawKind>,
+}
+
+// Kinds are looked up far more often than new ones are interned, so lookups
+// only take a shared lock.
+fn interner() -> &'static RwLock<Interner> {
+    static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(Default::default)
+}
@@ -1,40 +1,76 @@
 mod pure;
-mod sll;
+mod builder;
+mod cache;
+mod children;
+mod cursor;
+mod registry;
 mod delta;
+mod diff;
+mod shared;
+mod history;
+mod recorder;
+mod kind;
+mod syntax_text;
+mod text_size;
+mod utility_types;
+mod validate;
 
-use core::panic;
 use std::{
-    cell::{Cell, RefCell},
-    fmt, iter,
-    rc::{self, Rc},
+    collections::HashSet,
+    fmt, iter, mem,
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-pub use pure::{PureChild, PureChildKind, PureToken, PureTree, PureTreeData};
+use crate::{
+    recorder::Observer,
+    registry::Registry,
+    shared::{Cell, Rc, RefCell, TreeGuard, TreeLock, Weak},
+    validate::Report,
+};
+
+pub use crate::{
+    builder::{Checkpoint, PureTreeBuilder},
+    cache::{CacheStats, NodeCache},
+    cursor::{PureCursor, PureCursorPreorder},
+    diff::{diff, DiffOp, TreeDiff},
+    history::History,
+    kind::{Language, RawKind, StrLanguage},
+    pure::{
+        PureChild, PureChildKind, PurePreorder, PurePreorderWithTokens, PureToken, PureTree,
+        PureTreeData,
+    },
+    recorder::{MutationRecorder, TextEdit},
+    syntax_text::SyntaxText,
+    text_size::{TextRange, TextSize},
+    utility_types::{Direction, TokenAtOffset, WalkEvent},
+    validate::ValidationError,
+};
 
-#[derive(Clone, PartialEq, Eq)]
-pub struct SyntaxTree {
-    data: Rc<SyntaxData>,
+#[derive(PartialEq, Eq)]
+pub struct SyntaxTree<L: Language = StrLanguage> {
+    data: Rc<SyntaxData<L>>,
 }
 
-#[derive(Clone, PartialEq, Eq)]
-pub struct SyntaxToken {
-    data: Rc<SyntaxData>,
+#[derive(PartialEq, Eq)]
+pub struct SyntaxToken<L: Language = StrLanguage> {
+    data: Rc<SyntaxData<L>>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum SyntaxChild {
-    Tree(SyntaxTree),
-    Token(SyntaxToken),
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyntaxChild<L: Language = StrLanguage> {
+    Tree(SyntaxTree<L>),
+    Token(SyntaxToken<L>),
 }
 
-impl From<SyntaxToken> for SyntaxChild {
-    fn from(v: SyntaxToken) -> Self {
+impl<L: Language> From<SyntaxToken<L>> for SyntaxChild<L> {
+    fn from(v: SyntaxToken<L>) -> Self {
         SyntaxChild::Token(v)
     }
 }
 
-impl From<SyntaxTree> for SyntaxChild {
-    fn from(v: SyntaxTree) -> Self {
+impl<L: Language> From<SyntaxTree<L>> for SyntaxChild<L> {
+    fn from(v: SyntaxTree<L>) -> Self {
         SyntaxChild::Tree(v)
     }
 }
@@ -44,88 +80,122 @@ enum Pure {
     Token(PureToken),
 }
 
-struct SyntaxData {
+struct SyntaxData<L: Language> {
     pure: Pure,
 
-    parent: Cell<Option<SyntaxTree>>,
-    index: Cell<usize>,
+    // Counts as a handle to the parent.
+    parent: Cell<Option<Rc<SyntaxData<L>>>>,
+    // Slot in the parent's `children`, the key of which is our index.
+    slot: Cell<usize>,
 
-    first: Cell<rc::Weak<SyntaxData>>,
-    // Invariant: never null
-    next: Cell<rc::Weak<SyntaxData>>,
-    prev: Cell<rc::Weak<SyntaxData>>,
-}
+    // Live children, keyed by index.
+    children: RefCell<Registry<Weak<SyntaxData<L>>>>,
 
-impl sll::Elem for SyntaxData {
-    fn prev(&self) -> &Cell<rc::Weak<Self>> {
-        &self.prev
-    }
-    fn next(&self) -> &Cell<rc::Weak<Self>> {
-        &self.next
-    }
-    fn key(&self) -> &Cell<usize> {
-        &self.index
-    }
+    observers: RefCell<Vec<Weak<dyn Observer>>>,
+    // Shared by all elements of the tree.
+    tree_lock: Cell<TreeLock>,
+    // Number of `SyntaxTree`/`SyntaxToken` handles, the data is a live child
+    // of its parent while it's positive.
+    handles: AtomicUsize,
 }
 
-impl SyntaxChild {
-    fn new(pure: PureChild, parent: SyntaxTree, index: usize) -> SyntaxChild {
-        let mut token = false;
-        let pure = match pure.kind {
+impl<L: Language> SyntaxChild<L> {
+    fn new(pure: PureChildKind) -> SyntaxChild<L> {
+        let pure = match pure {
             PureChildKind::Tree(it) => Pure::Tree(RefCell::new(it)),
-            PureChildKind::Token(it) => {
-                token = true;
-                Pure::Token(it)
-            }
+            PureChildKind::Token(it) => Pure::Token(it),
         };
         let data = SyntaxData {
             pure,
-            parent: Cell::new(Some(parent)),
-            index: Cell::new(index),
-            first: Default::default(),
-            next: Default::default(),
-            prev: Default::default(),
+            parent: Default::default(),
+            slot: Default::default(),
+            children: Default::default(),
+            observers: Default::default(),
+            tree_lock: Default::default(),
+            handles: AtomicUsize::new(1),
         };
-        let data = Rc::new(data);
-        data.next.set(Rc::downgrade(&data));
-        data.prev.set(Rc::downgrade(&data));
-        if token {
-            SyntaxChild::Token(SyntaxToken { data })
-        } else {
-            SyntaxChild::Tree(SyntaxTree { data })
+        SyntaxChild::from_data(Rc::new(data))
+    }
+    /// Wraps `data`, which must already be counted as a handle.
+    fn from_data(data: Rc<SyntaxData<L>>) -> SyntaxChild<L> {
+        match data.pure {
+            Pure::Tree(_) => SyntaxChild::Tree(SyntaxTree { data }),
+            Pure::Token(_) => SyntaxChild::Token(SyntaxToken { data }),
         }
     }
-    pub fn kind(&self) -> &'static str {
+    pub fn as_tree(&self) -> Option<&SyntaxTree<L>> {
+        match self {
+            SyntaxChild::Tree(it) => Some(it),
+            SyntaxChild::Token(_) => None,
+        }
+    }
+    pub fn as_token(&self) -> Option<&SyntaxToken<L>> {
+        match self {
+            SyntaxChild::Tree(_) => None,
+            SyntaxChild::Token(it) => Some(it),
+        }
+    }
+    pub fn into_tree(self) -> Option<SyntaxTree<L>> {
+        match self {
+            SyntaxChild::Tree(it) => Some(it),
+            SyntaxChild::Token(_) => None,
+        }
+    }
+    pub fn into_token(self) -> Option<SyntaxToken<L>> {
+        match self {
+            SyntaxChild::Tree(_) => None,
+            SyntaxChild::Token(it) => Some(it),
+        }
+    }
+    pub fn kind(&self) -> L::Kind {
         match self {
             SyntaxChild::Tree(it) => it.kind(),
             SyntaxChild::Token(it) => it.kind(),
         }
     }
-    pub fn offset(&self) -> usize {
+    pub fn offset(&self) -> TextSize {
         match self {
             SyntaxChild::Tree(it) => it.offset(),
             SyntaxChild::Token(it) => it.offset(),
         }
     }
-    pub fn text_len(&self) -> usize {
+    pub fn text_len(&self) -> TextSize {
         match self {
             SyntaxChild::Tree(it) => it.text_len(),
             SyntaxChild::Token(it) => it.text_len(),
         }
     }
-    pub fn parent(&self) -> Option<SyntaxTree> {
+    pub fn text_range(&self) -> TextRange {
+        match self {
+            SyntaxChild::Tree(it) => it.text_range(),
+            SyntaxChild::Token(it) => it.text_range(),
+        }
+    }
+    pub fn parent(&self) -> Option<SyntaxTree<L>> {
         match self {
             SyntaxChild::Tree(it) => it.parent(),
             SyntaxChild::Token(it) => it.parent(),
         }
     }
-    pub fn next_sibling(&self) -> Option<SyntaxChild> {
+    pub fn ancestors(&self) -> impl Iterator<Item = SyntaxTree<L>> {
+        iter::successors(self.parent(), SyntaxTree::parent)
+    }
+    pub fn index(&self) -> usize {
+        match self {
+            SyntaxChild::Tree(it) => it.index(),
+            SyntaxChild::Token(it) => it.index(),
+        }
+    }
+    pub fn siblings(&self, direction: Direction) -> impl Iterator<Item = SyntaxChild<L>> {
+        iter::successors(Some(self.clone()), move |it| it.sibling(direction))
+    }
+    pub fn next_sibling(&self) -> Option<SyntaxChild<L>> {
         match self {
             SyntaxChild::Tree(it) => it.next_sibling(),
             SyntaxChild::Token(it) => it.next_sibling(),
         }
     }
-    pub fn prev_sibling(&self) -> Option<SyntaxChild> {
+    pub fn prev_sibling(&self) -> Option<SyntaxChild<L>> {
         match self {
             SyntaxChild::Tree(it) => it.prev_sibling(),
             SyntaxChild::Token(it) => it.prev_sibling(),
@@ -137,7 +207,55 @@ impl SyntaxChild {
             SyntaxChild::Token(it) => it.detach(),
         }
     }
-    fn data_mut(&mut self) -> &mut Rc<SyntaxData> {
+    /// Replaces this element with `new` in the parent, keeping the indices of
+    /// the siblings. `self` becomes a standalone tree or token.
+    ///
+    /// Panics if `self` is a root, or `new` has a parent or is the root of
+    /// `self`.
+    pub fn replace_with(&self, new: SyntaxChild<L>) {
+        match self {
+            SyntaxChild::Tree(it) => it.replace_with(new),
+            SyntaxChild::Token(it) => it.replace_with(new),
+        }
+    }
+    pub fn snapshot(&self) -> PureChildKind {
+        match self {
+            SyntaxChild::Tree(it) => it.snapshot().into(),
+            SyntaxChild::Token(it) => it.snapshot().into(),
+        }
+    }
+    fn sibling(&self, direction: Direction) -> Option<SyntaxChild<L>> {
+        match direction {
+            Direction::Next => self.next_sibling(),
+            Direction::Prev => self.prev_sibling(),
+        }
+    }
+    /// The sibling in `direction`, or the sibling of the closest ancestor
+    /// which has one, without leaving `stop`.
+    fn step_out(
+        &self,
+        direction: Direction,
+        stop: Option<&SyntaxTree<L>>,
+    ) -> Option<SyntaxChild<L>> {
+        let mut curr = self.clone();
+        loop {
+            if let Some(it) = curr.sibling(direction) {
+                return Some(it);
+            }
+            let parent = curr.parent()?;
+            if Some(&parent) == stop {
+                return None;
+            }
+            curr = parent.into();
+        }
+    }
+    fn data(&self) -> &Rc<SyntaxData<L>> {
+        match self {
+            SyntaxChild::Tree(it) => &it.data,
+            SyntaxChild::Token(it) => &it.data,
+        }
+    }
+    fn data_mut(&mut self) -> &mut Rc<SyntaxData<L>> {
         match self {
             SyntaxChild::Tree(it) => &mut it.data,
             SyntaxChild::Token(it) => &mut it.data,
@@ -145,32 +263,71 @@ impl SyntaxChild {
     }
 }
 
-impl SyntaxToken {
-    pub fn kind(&self) -> &'static str {
-        self.data.kind()
+impl<L: Language> SyntaxToken<L> {
+    pub fn kind(&self) -> L::Kind {
+        L::kind_from_raw(self.data.kind())
     }
     pub fn text(&self) -> &str {
         self.pure().text()
     }
-    pub fn offset(&self) -> usize {
+    pub fn offset(&self) -> TextSize {
         self.data.offset()
     }
-    pub fn text_len(&self) -> usize {
+    pub fn text_len(&self) -> TextSize {
         self.data.text_len()
     }
-    pub fn parent(&self) -> Option<SyntaxTree> {
+    pub fn text_range(&self) -> TextRange {
+        self.data.text_range()
+    }
+    pub fn parent(&self) -> Option<SyntaxTree<L>> {
         self.data.parent()
     }
-    pub fn next_sibling(&self) -> Option<SyntaxChild> {
+    pub fn ancestors(&self) -> impl Iterator<Item = SyntaxTree<L>> {
+        iter::successors(self.parent(), SyntaxTree::parent)
+    }
+    pub fn index(&self) -> usize {
+        self.data.index()
+    }
+    pub fn next_sibling(&self) -> Option<SyntaxChild<L>> {
         self.data.next_sibling()
     }
-    pub fn prev_sibling(&self) -> Option<SyntaxChild> {
+    pub fn prev_sibling(&self) -> Option<SyntaxChild<L>> {
         self.data.prev_sibling()
     }
+    pub fn siblings(&self, direction: Direction) -> impl Iterator<Item = SyntaxChild<L>> {
+        SyntaxChild::from(self.clone()).siblings(direction)
+    }
+    /// The next token in document order, possibly from a different subtree.
+    pub fn next_token(&self) -> Option<SyntaxToken<L>> {
+        self.neighbor_token(Direction::Next)
+    }
+    /// The previous token in document order, possibly from a different subtree.
+    pub fn prev_token(&self) -> Option<SyntaxToken<L>> {
+        self.neighbor_token(Direction::Prev)
+    }
+    pub fn replace_with(&self, new: SyntaxChild<L>) {
+        self.data.replace_with(new)
+    }
     pub fn detach(&self) {
         self.data.detach()
     }
 
+    fn neighbor_token(&self, direction: Direction) -> Option<SyntaxToken<L>> {
+        let mut curr = SyntaxChild::from(self.clone()).step_out(direction, None)?;
+        loop {
+            let tree = match curr {
+                SyntaxChild::Token(it) => return Some(it),
+                SyntaxChild::Tree(it) => it,
+            };
+            if let Some(it) = tree.edge_token(direction) {
+                return Some(it);
+            }
+            curr = SyntaxChild::from(tree).step_out(direction, None)?;
+        }
+    }
+    pub fn snapshot(&self) -> PureToken {
+        self.pure().clone()
+    }
     fn pure(&self) -> &PureToken {
         match &self.data.pure {
             Pure::Tree(_) => unreachable!(),
@@ -179,100 +336,398 @@ impl SyntaxToken {
     }
 }
 
-impl SyntaxTree {
-    fn new(pure: PureTree) -> SyntaxTree {
-        let data = SyntaxData {
-            pure: Pure::Tree(RefCell::new(pure)),
-            parent: Cell::new(None),
-            index: Cell::new(0),
-            first: Default::default(),
-            next: Default::default(),
-            prev: Default::default(),
-        };
-        let data = Rc::new(data);
-        data.next.set(Rc::downgrade(&data));
-        data.prev.set(Rc::downgrade(&data));
-        SyntaxTree { data }
+impl<L: Language> SyntaxTree<L> {
+    pub fn kind(&self) -> L::Kind {
+        L::kind_from_raw(self.data.kind())
     }
-    pub fn kind(&self) -> &'static str {
-        self.data.kind()
-    }
-    pub fn offset(&self) -> usize {
+    pub fn offset(&self) -> TextSize {
         self.data.offset()
     }
-    pub fn text_len(&self) -> usize {
+    pub fn text_len(&self) -> TextSize {
         self.data.text_len()
     }
-    pub fn parent(&self) -> Option<SyntaxTree> {
+    pub fn text_range(&self) -> TextRange {
+        self.data.text_range()
+    }
+    pub fn text(&self) -> SyntaxText {
+        SyntaxText::new(self.snapshot())
+    }
+    pub fn parent(&self) -> Option<SyntaxTree<L>> {
         self.data.parent()
     }
-    pub fn first_child(&self) -> Option<SyntaxChild> {
+    /// This tree and all its ancestors, up to the root.
+    pub fn ancestors(&self) -> impl Iterator<Item = SyntaxTree<L>> {
+        iter::successors(Some(self.clone()), SyntaxTree::parent)
+    }
+    pub fn root(&self) -> SyntaxTree<L> {
+        self.ancestors().last().unwrap()
+    }
+    pub fn index(&self) -> usize {
+        self.data.index()
+    }
+    pub fn children_count(&self) -> usize {
+        self.pure().borrow().children_count()
+    }
+    pub fn first_child(&self) -> Option<SyntaxChild<L>> {
         self.get_child(0)
     }
-    pub fn next_sibling(&self) -> Option<SyntaxChild> {
+    pub fn last_child(&self) -> Option<SyntaxChild<L>> {
+        let index = self.children_count().checked_sub(1)?;
+        self.get_child(index)
+    }
+    pub fn first_token(&self) -> Option<SyntaxToken<L>> {
+        self.edge_token(Direction::Next)
+    }
+    pub fn last_token(&self) -> Option<SyntaxToken<L>> {
+        self.edge_token(Direction::Prev)
+    }
+    pub fn next_sibling(&self) -> Option<SyntaxChild<L>> {
         self.data.next_sibling()
     }
-    pub fn prev_sibling(&self) -> Option<SyntaxChild> {
+    pub fn prev_sibling(&self) -> Option<SyntaxChild<L>> {
         self.data.prev_sibling()
     }
-    fn get_child(&self, index: usize) -> Option<SyntaxChild> {
-        let pure = self.pure().borrow().get_child(index).cloned()?;
-        let mut res = SyntaxChild::new(pure, self.clone(), index);
-        sll::link(&self.data.first, res.data_mut());
+    /// This tree and its siblings in the given direction.
+    pub fn siblings(&self, direction: Direction) -> impl Iterator<Item = SyntaxChild<L>> {
+        SyntaxChild::from(self.clone()).siblings(direction)
+    }
+    /// The first token of this subtree when walking in `direction`.
+    fn edge_token(&self, direction: Direction) -> Option<SyntaxToken<L>> {
+        let edge_child = |tree: &SyntaxTree<L>| match direction {
+            Direction::Next => tree.first_child(),
+            Direction::Prev => tree.last_child(),
+        };
+        let mut curr = edge_child(self)?;
+        loop {
+            curr = match curr {
+                SyntaxChild::Token(it) => return Some(it),
+                SyntaxChild::Tree(it) => match edge_child(&it) {
+                    Some(child) => child,
+                    None => SyntaxChild::from(it).step_out(direction, Some(self))?,
+                },
+            }
+        }
+    }
+    pub(crate) fn get_child(&self, index: usize) -> Option<SyntaxChild<L>> {
+        let _guard = self.data.lock();
+        let pure = self.pure().borrow().get_child(index)?;
+        let live = self.data.children.borrow().get(index).map(|it| it.upgrade().unwrap());
+        if let Some(data) = live {
+            return Some(SyntaxChild::from_data(data.acquire()));
+        }
+        let mut res = SyntaxChild::new(pure.kind);
+        self.attach(index, &mut res);
         Some(res)
     }
 
-    pub fn children(&self) -> impl Iterator<Item = SyntaxChild> {
+    pub fn children(&self) -> impl Iterator<Item = SyntaxChild<L>> {
         iter::successors(self.first_child(), |it| it.next_sibling())
     }
-    pub fn find_tree(&self, kind: &str) -> Option<SyntaxTree> {
-        let child = self.children().find(|it| it.kind() == kind)?;
-        match child {
-            SyntaxChild::Tree(it) => Some(it),
-            SyntaxChild::Token(_) => panic!(),
+    pub fn preorder(&self) -> Preorder<L> {
+        Preorder { inner: self.preorder_with_tokens() }
+    }
+    pub fn preorder_with_tokens(&self) -> PreorderWithTokens<L> {
+        let start: SyntaxChild<L> = self.clone().into();
+        PreorderWithTokens { next: Some(WalkEvent::Enter(start.clone())), start }
+    }
+    /// All trees of this subtree, including this one, in preorder.
+    pub fn descendants(&self) -> impl Iterator<Item = SyntaxTree<L>> {
+        self.preorder().filter_map(|event| match event {
+            WalkEvent::Enter(it) => Some(it),
+            WalkEvent::Leave(_) => None,
+        })
+    }
+    /// All elements of this subtree, including this tree, in preorder.
+    pub fn descendants_with_tokens(&self) -> impl Iterator<Item = SyntaxChild<L>> {
+        self.preorder_with_tokens().filter_map(|event| match event {
+            WalkEvent::Enter(it) => Some(it),
+            WalkEvent::Leave(_) => None,
+        })
+    }
+    /// The first child tree of the given kind. Tokens are skipped, even if
+    /// they have the same kind.
+    pub fn find_tree(&self, kind: L::Kind) -> Option<SyntaxTree<L>> {
+        let kind = L::kind_to_raw(kind);
+        self.children().filter_map(SyntaxChild::into_tree).find(|it| it.data.kind() == kind)
+    }
+    /// The first child token of the given kind. Trees are skipped, even if
+    /// they have the same kind.
+    pub fn find_token(&self, kind: L::Kind) -> Option<SyntaxToken<L>> {
+        let kind = L::kind_to_raw(kind);
+        self.children().filter_map(SyntaxChild::into_token).find(|it| it.data.kind() == kind)
+    }
+
+    /// Finds the token(s) at `offset`, which must be within the tree's range.
+    pub fn token_at_offset(&self, offset: TextSize) -> TokenAtOffset<SyntaxToken<L>> {
+        let range = self.text_range();
+        assert!(range.contains_inclusive(offset), "bad offset: {:?} not in {:?}", offset, range);
+        let _guard = self.data.lock();
+        let mut tree = self.clone();
+        let mut start = range.start();
+        loop {
+            let (left, right) = {
+                let pure = tree.pure().borrow();
+                let mut touching = pure
+                    .children_touching(offset - start)
+                    .filter(|(_, it)| !it.text_range().is_empty())
+                    .map(|(idx, it)| (idx, it.offset));
+                let left = touching.next();
+                let right = touching.next();
+                assert!(touching.next().is_none());
+                (left, right)
+            };
+            let (idx, child_offset) = match (left, right) {
+                (None, _) => return TokenAtOffset::None,
+                (Some((left, _)), Some((right, _))) => {
+                    let single = |idx| match tree.get_child(idx).unwrap() {
+                        SyntaxChild::Token(it) => it,
+                        SyntaxChild::Tree(it) => match it.token_at_offset(offset) {
+                            TokenAtOffset::Single(it) => it,
+                            _ => unreachable!(),
+                        },
+                    };
+                    return TokenAtOffset::Between(single(left), single(right));
+                }
+                (Some(left), None) => left,
+            };
+            match tree.get_child(idx).unwrap() {
+                SyntaxChild::Token(it) => return TokenAtOffset::Single(it),
+                SyntaxChild::Tree(it) => {
+                    tree = it;
+                    start += child_offset;
+                }
+            }
         }
     }
-    pub fn find_token(&self, kind: &str) -> Option<SyntaxToken> {
-        let child = self.children().find(|it| it.kind() == kind)?;
-        match child {
-            SyntaxChild::Tree(_) => panic!(),
-            SyntaxChild::Token(it) => Some(it),
+    /// Finds the smallest element whose range contains `range`, which must
+    /// be within the tree's range.
+    pub fn covering_element(&self, range: TextRange) -> SyntaxChild<L> {
+        let self_range = self.text_range();
+        assert!(self_range.contains_range(range), "bad range: {:?} not in {:?}", range, self_range);
+        let _guard = self.data.lock();
+        let mut tree = self.clone();
+        let mut start = self_range.start();
+        loop {
+            let found = tree
+                .pure()
+                .borrow()
+                .child_covering(range - start)
+                .map(|(idx, it)| (idx, it.offset));
+            let (idx, child_offset) = match found {
+                Some(it) => it,
+                None => return tree.into(),
+            };
+            match tree.get_child(idx).unwrap() {
+                SyntaxChild::Token(it) => return it.into(),
+                SyntaxChild::Tree(it) => {
+                    tree = it;
+                    start += child_offset;
+                }
+            }
         }
     }
 
-    pub fn insert_child(&self, index: usize, mut child: SyntaxChild) {
+    /// The child containing `offset`. Empty children never contain an
+    /// offset.
+    pub fn child_at_offset(&self, offset: TextSize) -> Option<SyntaxChild<L>> {
+        let _guard = self.data.lock();
+        let offset = offset.checked_sub(self.offset())?;
+        let idx = self.pure().borrow().child_at_offset(offset)?.0;
+        self.get_child(idx)
+    }
+    /// Children intersecting `range`, not counting those which only touch
+    /// its ends. `range` must be within the tree's range.
+    pub fn children_in_range(&self, range: TextRange) -> impl Iterator<Item = SyntaxChild<L>> {
+        let self_range = self.text_range();
+        assert!(self_range.contains_range(range), "bad range: {:?} not in {:?}", range, self_range);
+        let indices = self.pure().borrow().children_range(range - self_range.start());
+        let tree = self.clone();
+        indices.filter_map(move |idx| tree.get_child(idx))
+    }
+    pub fn insert_child(&self, index: usize, mut child: SyntaxChild<L>) {
+        let _guard = shared::lock_all(|| {
+            vec![self.data.tree_lock.cloned(), child.data().tree_lock.cloned()]
+        });
         assert!(child.parent().is_none());
-        let weak = self.data.first.take();
-        let first = weak.upgrade();
-        self.data.first.set(weak);
-        if let Some(first) = first {
-            sll::adjust(&first, index, 1);
-        }
-        sll::link(
-            &self.data.first,
-            match &mut child {
-                SyntaxChild::Tree(it) => &mut it.data,
-                SyntaxChild::Token(it) => &mut it.data,
-            },
+        assert!(
+            !child.data().is_ancestor_of(&self.data),
+            "can't insert the root of a tree into that tree"
+        );
+        self.data.children.borrow_mut().shift(index, 1);
+        let pure = self.pure().borrow().insert_child(index, child.snapshot());
+        self.attach(index, &mut child);
+        self.replace_pure(pure, index..index, 1)
+    }
+    /// Replaces children in `range` with `children`, like `Vec::splice`.
+    ///
+    /// Live handles to the removed children become standalone, handles to
+    /// the following children are shifted. The tree is rebuilt only once.
+    ///
+    /// Panics if an element of `children` has a parent, appears twice, or is
+    /// the root of `self`.
+    pub fn splice_children(
+        &self,
+        range: Range<usize>,
+        children: impl IntoIterator<Item = SyntaxChild<L>>,
+    ) {
+        let mut children: Vec<SyntaxChild<L>> = children.into_iter().collect();
+        let _guard = shared::lock_all(|| {
+            let data = iter::once(&self.data).chain(children.iter().map(SyntaxChild::data));
+            data.map(|it| it.tree_lock.cloned()).collect()
+        });
+        let mut seen = HashSet::new();
+        assert!(
+            children.iter().all(|it| it.parent().is_none() && seen.insert(Rc::as_ptr(it.data()))),
+            "children must be distinct detached elements"
         );
+        assert!(
+            !children.iter().any(|it| it.data().is_ancestor_of(&self.data)),
+            "can't splice the root of a tree into that tree"
+        );
+        let pure = self
+            .pure()
+            .borrow()
+            .splice_children(range.clone(), children.iter().map(SyntaxChild::snapshot));
 
-        let pure_child = match child {
-            SyntaxChild::Tree(it) => it.pure().borrow().clone().into(),
-            SyntaxChild::Token(it) => it.pure().clone().into(),
-        };
-        let pure = self.pure().borrow().insert_child(index, pure_child);
-        self.replace_pure(pure)
+        for (_, removed) in self.data.live_children(range.clone()) {
+            removed.unlink();
+        }
+        let delta = children.len() as isize - range.len() as isize;
+        self.data.children.borrow_mut().shift(range.end, delta);
+        for (idx, child) in children.iter_mut().enumerate() {
+            self.attach(range.start + idx, child);
+        }
+        self.replace_pure(pure, range, children.len())
+    }
+    pub fn replace_with(&self, new: SyntaxChild<L>) {
+        self.data.replace_with(new)
     }
     pub fn detach(&self) {
         self.data.detach()
     }
-    fn replace_pure(&self, mut pure: PureTree) {
+    /// Registers a detached `child` as a live child at `index`.
+    fn attach(&self, index: usize, child: &mut SyntaxChild<L>) {
+        let data = child.data_mut();
+        data.set_tree_lock(self.data.tree_lock.cloned());
+        data.parent.set(Some(self.data.acquire()));
+        data.slot.set(self.data.children.borrow_mut().insert(index, Rc::downgrade(data)));
+    }
+    /// The current state of the tree as an immutable value.
+    ///
+    /// The snapshot is unaffected by later mutations and, unlike the tree
+    /// itself, can be sent to other threads.
+    pub fn snapshot(&self) -> PureTree {
+        self.pure().borrow().clone()
+    }
+    /// Checks the invariants of the pure tree (see [`PureTree::validate`])
+    /// and of the live handles below this tree: each of them is registered
+    /// under its index in its parent, and its pure element is the child of
+    /// the parent's pure tree at that index.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let _guard = self.data.lock();
+        let mut report = Report::default();
+        self.snapshot().validate_into(&mut report);
+
+        let mut path = Vec::new();
+        // Trees being checked, with their remaining live children.
+        let mut stack = vec![(self.data.clone(), self.data.validate_children(&path, &mut report))];
+        while let Some((data, children)) = stack.last_mut() {
+            let (index, child) = match children.next() {
+                Some(it) => it,
+                None => {
+                    stack.pop();
+                    path.pop();
+                    continue;
+                }
+            };
+            let pure = data.pure_tree().borrow().get_child(index).map(|it| it.kind);
+            path.push(index);
+            if !child.parent.cloned().is_some_and(|it| Rc::ptr_eq(&it, data)) {
+                report.error(&path, "live child with a different parent");
+            }
+            if child.handles.load(Ordering::Relaxed) == 0 {
+                report.error(&path, "live child without handles");
+            }
+            let matches = match (&child.pure, &pure) {
+                (Pure::Tree(it), Some(PureChildKind::Tree(pure))) => *it.borrow() == *pure,
+                (Pure::Token(it), Some(PureChildKind::Token(pure))) => it == pure,
+                _ => false,
+            };
+            if !matches {
+                report.error(&path, "live child differs from the pure child at its index");
+            }
+            match child.pure {
+                Pure::Tree(_) => {
+                    let children = child.validate_children(&path, &mut report);
+                    stack.push((child, children));
+                }
+                Pure::Token(_) => {
+                    path.pop();
+                }
+            }
+        }
+        report.finish()
+    }
+    pub(crate) fn add_observer(&self, observer: Weak<dyn Observer>) {
+        self.data.observers.borrow_mut().push(observer)
+    }
+    /// Swaps the whole pure tree, keeping live descendants whose index path
+    /// still leads to an element of the same kind and detaching the rest.
+    /// Observers of the kept descendants are notified too.
+    pub(crate) fn reset_pure(&self, pure: PureTree) {
+        let _guard = self.data.lock();
+        let mut stack = vec![(self.data.clone(), pure.clone())];
+        while let Some((data, pure)) = stack.pop() {
+            for (index, child) in data.live_children(0..usize::MAX) {
+                let new = pure.get_child(index).map(|it| it.kind);
+                match (&child.pure, &new) {
+                    (Pure::Tree(old), Some(PureChildKind::Tree(new)))
+                        if old.borrow().kind() == new.kind() =>
+                    {
+                        let old = mem::replace(&mut *old.borrow_mut(), new.clone());
+                        // Only the root goes through `replace_pure`, so
+                        // observers of descendants are notified here.
+                        if !old.ptr_eq(new) && !child.observers.borrow().is_empty() {
+                            let replaced = 0..old.children_count();
+                            let (range, new_len) =
+                                recorder::changed_range(&old, new, replaced, new.children_count());
+                            child.notify(range, new_len, new);
+                        }
+                        stack.push((child.clone(), new.clone()));
+                    }
+                    (Pure::Token(old), Some(PureChildKind::Token(new))) if old == new => (),
+                    _ => child.unlink(),
+                }
+            }
+        }
+        let new_count = pure.children_count();
+        self.replace_pure(pure, 0..self.children_count(), new_count)
+    }
+    /// Sets the pure tree of this node and its ancestors, after `replaced`
+    /// children were replaced with `new_count` ones.
+    fn replace_pure(&self, mut pure: PureTree, replaced: Range<usize>, new_count: usize) {
+        // Computing the changed range compares children, which is only worth
+        // it if someone listens.
+        let observed = iter::successors(Some(self.clone()), |it| it.parent())
+            .any(|it| !it.data.observers.borrow().is_empty());
+        let mut change = if observed {
+            Some(recorder::changed_range(&self.pure().borrow(), &pure, replaced, new_count))
+        } else {
+            None
+        };
         let mut node = self.clone();
         loop {
             *node.pure().borrow_mut() = pure.clone();
+            if let Some((range, new_len)) = change {
+                node.data.notify(range, new_len, &pure);
+            }
             match node.parent() {
                 Some(parent) => {
-                    pure = parent.pure().borrow().replace_child(node.data.index.get(), pure.into());
+                    let index = node.data.index();
+                    pure = parent.pure().borrow().replace_child(index, pure.into());
+                    if let Some((range, _)) = &mut change {
+                        *range = *range + pure.get_child(index).unwrap().offset;
+                    }
                     node = parent
                 }
                 None => return,
@@ -281,113 +736,402 @@ impl SyntaxTree {
     }
 
     fn pure(&self) -> &RefCell<PureTree> {
-        match &self.data.pure {
-            Pure::Tree(it) => it,
-            Pure::Token(_) => unreachable!(),
+        self.data.pure_tree()
+    }
+}
+
+pub struct PreorderWithTokens<L: Language = StrLanguage> {
+    start: SyntaxChild<L>,
+    next: Option<WalkEvent<SyntaxChild<L>>>,
+}
+
+impl<L: Language> PreorderWithTokens<L> {
+    /// Skips the children of the tree which was just entered.
+    pub fn skip_subtree(&mut self) {
+        self.next = self.next.take().map(|next| match next {
+            WalkEvent::Enter(first_child) => WalkEvent::Leave(first_child.parent().unwrap().into()),
+            WalkEvent::Leave(parent) => WalkEvent::Leave(parent),
+        })
+    }
+}
+
+impl<L: Language> Iterator for PreorderWithTokens<L> {
+    type Item = WalkEvent<SyntaxChild<L>>;
+
+    fn next(&mut self) -> Option<WalkEvent<SyntaxChild<L>>> {
+        let next = self.next.take()?;
+        self.next = match &next {
+            WalkEvent::Enter(child) => {
+                let first_child = match child {
+                    SyntaxChild::Tree(it) => it.first_child(),
+                    SyntaxChild::Token(_) => None,
+                };
+                Some(match first_child {
+                    Some(it) => WalkEvent::Enter(it),
+                    None => WalkEvent::Leave(child.clone()),
+                })
+            }
+            WalkEvent::Leave(child) if *child == self.start => None,
+            WalkEvent::Leave(child) => match child.next_sibling() {
+                Some(it) => Some(WalkEvent::Enter(it)),
+                None => child.parent().map(|it| WalkEvent::Leave(it.into())),
+            },
+        };
+        Some(next)
+    }
+}
+
+pub struct Preorder<L: Language = StrLanguage> {
+    inner: PreorderWithTokens<L>,
+}
+
+impl<L: Language> Preorder<L> {
+    /// Skips the children of the tree which was just entered.
+    pub fn skip_subtree(&mut self) {
+        self.inner.skip_subtree()
+    }
+}
+
+impl<L: Language> Iterator for Preorder<L> {
+    type Item = WalkEvent<SyntaxTree<L>>;
+
+    fn next(&mut self) -> Option<WalkEvent<SyntaxTree<L>>> {
+        loop {
+            let event = match self.inner.next()? {
+                WalkEvent::Enter(SyntaxChild::Tree(it)) => WalkEvent::Enter(it),
+                WalkEvent::Leave(SyntaxChild::Tree(it)) => WalkEvent::Leave(it),
+                WalkEvent::Enter(SyntaxChild::Token(_))
+                | WalkEvent::Leave(SyntaxChild::Token(_)) => continue,
+            };
+            return Some(event);
         }
     }
 }
 
-impl Drop for SyntaxTree {
+impl<L: Language> Clone for SyntaxTree<L> {
+    fn clone(&self) -> Self {
+        SyntaxTree { data: self.data.acquire() }
+    }
+}
+
+impl<L: Language> Clone for SyntaxToken<L> {
+    fn clone(&self) -> Self {
+        SyntaxToken { data: self.data.acquire() }
+    }
+}
+
+impl<L: Language> Drop for SyntaxTree<L> {
     fn drop(&mut self) {
-        if Rc::strong_count(&self.data) == 1 {
-            assert!(self.data.first.take().strong_count() == 0);
-            self.data.unlink()
-        }
+        self.data.release()
     }
 }
 
-impl Drop for SyntaxToken {
+impl<L: Language> Drop for SyntaxToken<L> {
     fn drop(&mut self) {
-        if Rc::strong_count(&self.data) == 1 {
-            assert!(self.data.first.take().strong_count() == 0);
-            self.data.unlink()
-        }
+        self.data.release()
     }
 }
 
-impl From<PureTree> for SyntaxTree {
+impl<L: Language> From<PureTree> for SyntaxTree<L> {
     fn from(pure: PureTree) -> Self {
-        SyntaxTree::new(pure)
+        SyntaxChild::new(pure.into()).into_tree().unwrap()
     }
 }
 
-impl fmt::Debug for SyntaxTree {
+impl<L: Language> From<PureToken> for SyntaxToken<L> {
+    fn from(pure: PureToken) -> Self {
+        SyntaxChild::new(pure.into()).into_token().unwrap()
+    }
+}
+
+impl<L: Language> From<PureChildKind> for SyntaxChild<L> {
+    fn from(pure: PureChildKind) -> Self {
+        SyntaxChild::new(pure)
+    }
+}
+
+impl<L: Language> fmt::Debug for SyntaxTree<L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.pure().borrow().debug_fmt(f, &fmt_kind::<L>)
+    }
+}
+
+impl<L: Language> fmt::Debug for SyntaxToken<L> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(&*self.pure().borrow(), f)
+        self.pure().debug_fmt(f, &fmt_kind::<L>)
     }
 }
 
-impl fmt::Debug for SyntaxToken {
+fn fmt_kind<L: Language>(kind: RawKind, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match L::try_kind_from_raw(kind) {
+        Some(it) => fmt::Debug::fmt(&it, f),
+        None => write!(f, "{:?}", kind),
+    }
+}
+
+impl<L: Language> fmt::Display for SyntaxTree<L> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(&*self.pure(), f)
+        fmt::Display::fmt(&*self.pure().borrow(), f)
     }
 }
 
-impl SyntaxData {
-    fn kind(&self) -> &'static str {
+impl<L: Language> fmt::Display for SyntaxToken<L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.pure(), f)
+    }
+}
+
+impl<L: Language> fmt::Display for SyntaxChild<L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxChild::Tree(it) => fmt::Display::fmt(it, f),
+            SyntaxChild::Token(it) => fmt::Display::fmt(it, f),
+        }
+    }
+}
+
+impl<L: Language> SyntaxData<L> {
+    fn kind(&self) -> RawKind {
         match &self.pure {
             Pure::Tree(it) => it.borrow().kind(),
             Pure::Token(it) => it.kind(),
         }
     }
-    fn offset(&self) -> usize {
-        let mut offset = 0;
-        if let Some(parent) = self.parent() {
-            let idx = self.index.get();
-            offset += parent.offset();
-            offset += parent.pure().borrow().get_child(idx).unwrap().offset;
+    fn pure_tree(&self) -> &RefCell<PureTree> {
+        match &self.pure {
+            Pure::Tree(it) => it,
+            Pure::Token(_) => unreachable!(),
+        }
+    }
+    fn offset(&self) -> TextSize {
+        let _guard = self.lock();
+        let mut offset = TextSize::default();
+        let mut index = self.index();
+        let mut parent = self.parent.cloned();
+        while let Some(data) = parent {
+            offset += data.pure_tree().borrow().get_child(index).unwrap().offset;
+            index = data.index();
+            parent = data.parent.cloned();
         }
         offset
     }
-    fn text_len(&self) -> usize {
+    fn text_len(&self) -> TextSize {
         match &self.pure {
             Pure::Tree(it) => it.borrow().text_len(),
             Pure::Token(it) => it.text_len(),
         }
     }
-    fn parent(&self) -> Option<SyntaxTree> {
-        let ret = self.parent.take();
-        self.parent.set(ret.clone());
-        ret
+    fn text_range(&self) -> TextRange {
+        TextRange::at(self.offset(), self.text_len())
     }
-    fn next_sibling(&self) -> Option<SyntaxChild> {
+    fn parent(&self) -> Option<SyntaxTree<L>> {
+        // Otherwise `self` could be detached and the parent released before
+        // it's acquired.
+        let _guard = self.lock();
+        let parent = self.parent.cloned()?;
+        Some(SyntaxTree { data: parent.acquire() })
+    }
+    fn index(&self) -> usize {
+        let _guard = self.lock();
+        match self.parent.cloned() {
+            Some(parent) => parent.children.borrow().key(self.slot.get()),
+            None => 0,
+        }
+    }
+    /// Checks the registry of live children, returning those which are still
+    /// alive.
+    fn validate_children(
+        &self,
+        path: &[usize],
+        report: &mut Report,
+    ) -> std::vec::IntoIter<(usize, Rc<SyntaxData<L>>)> {
+        let children = self.children.borrow();
+        for error in children.check() {
+            report.error(path, format!("live children: {}", error));
+        }
+        let mut res = Vec::new();
+        for (index, child) in children.in_range(0..usize::MAX) {
+            match child.upgrade() {
+                Some(it) if children.try_key(it.slot.get()) == Some(index) => res.push((index, it)),
+                Some(_) => report.error(path, format!("live child {} has a wrong slot", index)),
+                None => report.error(path, format!("live child {} was dropped", index)),
+            }
+        }
+        res.into_iter()
+    }
+    /// Live children with indices in `range`, in order.
+    fn live_children(&self, range: Range<usize>) -> Vec<(usize, Rc<SyntaxData<L>>)> {
+        let children = self.children.borrow();
+        let live = children.in_range(range).into_iter();
+        live.map(|(index, it)| (index, it.upgrade().unwrap())).collect()
+    }
+    fn next_sibling(&self) -> Option<SyntaxChild<L>> {
         let parent = self.parent()?;
-        let index = self.index.get() + 1;
+        let index = self.index() + 1;
         parent.get_child(index)
     }
-    fn prev_sibling(&self) -> Option<SyntaxChild> {
+    fn prev_sibling(&self) -> Option<SyntaxChild<L>> {
         let parent = self.parent()?;
-        let index = self.index.get().checked_sub(1)?;
+        let index = self.index().checked_sub(1)?;
         parent.get_child(index)
     }
-    fn detach(self: &Rc<SyntaxData>) {
+    fn replace_with(self: &Rc<SyntaxData<L>>, mut new: SyntaxChild<L>) {
+        let _guard =
+            shared::lock_all(|| vec![self.tree_lock.cloned(), new.data().tree_lock.cloned()]);
+        assert!(new.parent().is_none());
+        let parent = self.parent().expect("can't replace a root");
+        assert!(!new.data().is_ancestor_of(self), "can't replace an element with its own root");
+        let index = self.index();
+        let pure = parent.pure().borrow().replace_child(index, new.snapshot());
+        self.unlink();
+        parent.attach(index, &mut new);
+        parent.replace_pure(pure, index..index + 1, 1);
+    }
+    fn detach(self: &Rc<SyntaxData<L>>) {
+        let _guard = self.lock();
         if let Some(parent) = self.parent() {
-            let pure = parent.pure().borrow().remove_child(self.index.get());
-            parent.replace_pure(pure);
+            let index = self.index();
+            let pure = parent.pure().borrow().remove_child(index);
+            self.unlink();
+            parent.data.children.borrow_mut().shift(index, -1);
+            parent.replace_pure(pure, index..index + 1, 0);
         }
-        sll::adjust(&self, self.index.get() + 1, -1);
-        self.unlink();
     }
-    fn unlink(self: &Rc<SyntaxData>) {
-        let dummy;
-        let parent = self.parent.take();
-        let head = match parent.as_ref() {
-            Some(it) => &it.data.first,
-            None => {
-                dummy = Cell::new(rc::Weak::new());
-                &dummy
+    fn notify(&self, range: TextRange, new_len: TextSize, new: &PureTree) {
+        self.observers.borrow_mut().retain(|observer| match observer.upgrade() {
+            Some(it) => {
+                it.on_change(range, new_len, new);
+                true
             }
-        };
-        sll::unlink(head, &*self);
-        self.index.set(0);
+            None => false,
+        })
+    }
+    /// Whether `self` is `data` or one of its ancestors.
+    fn is_ancestor_of(&self, data: &Rc<SyntaxData<L>>) -> bool {
+        iter::successors(Some(data.clone()), |it| it.parent.cloned()).any(|it| *it == *self)
+    }
+    /// Locks the tree containing this data.
+    fn lock(&self) -> TreeGuard {
+        shared::lock_all(|| vec![self.tree_lock.cloned()])
+    }
+    /// Moves this data and its live descendants to the tree of `lock`.
+    fn set_tree_lock(self: &Rc<SyntaxData<L>>, lock: TreeLock) {
+        if cfg!(not(feature = "sync")) {
+            return;
+        }
+        let mut stack = vec![self.clone()];
+        while let Some(data) = stack.pop() {
+            data.tree_lock.set(lock.clone());
+            stack.extend(data.live_children(0..usize::MAX).into_iter().map(|(_, it)| it));
+        }
+    }
+    fn acquire(self: &Rc<SyntaxData<L>>) -> Rc<SyntaxData<L>> {
+        self.handles.fetch_add(1, Ordering::Relaxed);
+        self.clone()
+    }
+    fn release(self: &Rc<SyntaxData<L>>) {
+        // Other handles than the last one are released without locking.
+        let mut handles = self.handles.load(Ordering::Relaxed);
+        while handles > 1 {
+            let new = handles - 1;
+            match self.handles.compare_exchange(handles, new, Ordering::AcqRel, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(it) => handles = it,
+            }
+        }
+        let _guard = self.lock();
+        // Releasing the last handle releases the handle to the parent, which
+        // is done in a loop to support deep trees.
+        let mut data = self.clone();
+        while data.handles.fetch_sub(1, Ordering::AcqRel) == 1 {
+            assert!(data.children.borrow().is_empty());
+            data = match data.take_parent() {
+                Some(it) => it,
+                None => return,
+            };
+        }
+    }
+    fn unlink(self: &Rc<SyntaxData<L>>) {
+        if let Some(parent) = self.take_parent() {
+            // The new tree stays locked until all its elements have switched.
+            let lock = TreeLock::default();
+            lock.acquire();
+            self.set_tree_lock(lock.clone());
+            lock.release();
+            parent.release()
+        }
+    }
+    /// Removes this data from the live children of its parent, returning the
+    /// handle to the parent.
+    fn take_parent(&self) -> Option<Rc<SyntaxData<L>>> {
+        let parent = self.parent.take()?;
+        parent.children.borrow_mut().remove(self.slot.get());
+        Some(parent)
     }
 }
 
-impl PartialEq for SyntaxData {
+impl<L: Language> PartialEq for SyntaxData<L> {
     fn eq(&self, other: &Self) -> bool {
         std::ptr::eq(self, other)
     }
 }
 
-impl Eq for SyntaxData {}
+impl<L: Language> Eq for SyntaxData<L> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(tree: &SyntaxTree) -> Vec<String> {
+        tree.validate().map_or_else(|it| it.errors().to_vec(), |()| Vec::new())
+    }
+
+    fn make_tree() -> SyntaxTree {
+        let inner = PureTree::new("inner").push(PureToken::new("x", "b"));
+        let root = PureTree::new("root")
+            .push(PureToken::new("x", "a"))
+            .push(inner)
+            .push(PureToken::new("x", "c"));
+        PureTree::from(root).into()
+    }
+
+    #[test]
+    fn validate_slots() {
+        let tree = make_tree();
+        let (a, c) = (tree.get_child(0).unwrap(), tree.get_child(2).unwrap());
+        assert_eq!(errors(&tree), Vec::<String>::new());
+
+        let swap = || {
+            let slot = a.data().slot.get();
+            a.data().slot.set(c.data().slot.get());
+            c.data().slot.set(slot);
+        };
+        swap();
+        assert_eq!(
+            errors(&tree),
+            ["at []: live child 0 has a wrong slot", "at []: live child 2 has a wrong slot"]
+        );
+        swap();
+
+        let slot = tree.data.children.borrow_mut().insert(5, Weak::new());
+        assert_eq!(errors(&tree), ["at []: live child 5 was dropped"]);
+        tree.data.children.borrow_mut().remove(slot);
+        assert_eq!(errors(&tree), Vec::<String>::new());
+    }
+
+    #[test]
+    fn validate_parents() {
+        let tree = make_tree();
+        let inner = match tree.get_child(1).unwrap() {
+            SyntaxChild::Tree(it) => it,
+            SyntaxChild::Token(_) => unreachable!(),
+        };
+        let b = inner.get_child(0).unwrap();
+
+        let parent = b.data().parent.take();
+        b.data().parent.set(Some(tree.data.clone()));
+        assert_eq!(errors(&tree), ["at [1, 0]: live child with a different parent"]);
+        b.data().parent.set(parent);
+        assert_eq!(errors(&tree), Vec::<String>::new());
+    }
+}
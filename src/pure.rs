@@ -1,45 +1,142 @@
-use std::{fmt, mem, sync::Arc};
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Range,
+    sync::Arc,
+};
 
-use crate::delta::Delta;
+use crate::{
+    children::Children,
+    validate::{Report, ValidationError},
+    RawKind, TextRange, TextSize, WalkEvent,
+};
 
 #[derive(Clone)]
 pub struct PureTree {
     data: Arc<PureTreeData>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct PureTreeData {
-    kind: &'static str,
-    text_len: usize,
-    children: Vec<PureChild>,
+    kind: RawKind,
+    children: Children,
 }
 
 #[derive(Clone)]
 pub struct PureToken {
-    kind: &'static str,
-    text: String,
+    data: Arc<PureTokenData>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct PureTokenData {
+    kind: RawKind,
+    text: Box<str>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct PureChild {
-    pub offset: usize,
+    pub offset: TextSize,
     pub kind: PureChildKind,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum PureChildKind {
     Tree(PureTree),
     Token(PureToken),
 }
 
+// Trees which are about to be freed are taken apart in a loop, as dropping
+// them recursively overflows the stack on deep trees.
+impl Drop for PureTreeData {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        self.children.take_owned(&mut stack);
+        while let Some(child) = stack.pop() {
+            if let PureChildKind::Tree(tree) = child {
+                if let Ok(mut data) = Arc::try_unwrap(tree.data) {
+                    data.children.take_owned(&mut stack);
+                }
+            }
+        }
+    }
+}
+
+// Equality is structural, but elements sharing an allocation are equal
+// without looking inside, which makes comparing mostly shared trees cheap.
+impl PartialEq for PureTree {
+    fn eq(&self, other: &PureTree) -> bool {
+        let mut stack = vec![(self.clone(), other.clone())];
+        while let Some((tree, other)) = stack.pop() {
+            if tree.ptr_eq(&other) {
+                continue;
+            }
+            if tree.kind() != other.kind() {
+                return false;
+            }
+            if tree.data.children.shares_storage(&other.data.children) {
+                continue;
+            }
+            if tree.children_count() != other.children_count() {
+                return false;
+            }
+            for (child, other) in tree.children().zip(other.children()) {
+                match (child.kind, other.kind) {
+                    (PureChildKind::Tree(it), PureChildKind::Tree(other)) => {
+                        stack.push((it, other))
+                    }
+                    (PureChildKind::Token(it), PureChildKind::Token(other)) if it == other => (),
+                    _ => return false,
+                }
+            }
+        }
+        true
+    }
+}
+
+impl Eq for PureTree {}
+
+impl Hash for PureTree {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for event in self.preorder_with_tokens() {
+            match event {
+                WalkEvent::Enter(PureChildKind::Tree(it)) => {
+                    (it.kind(), it.children_count()).hash(state)
+                }
+                WalkEvent::Enter(PureChildKind::Token(it)) => it.hash(state),
+                WalkEvent::Leave(_) => (),
+            }
+        }
+    }
+}
+
+impl PartialEq for PureToken {
+    fn eq(&self, other: &PureToken) -> bool {
+        self.ptr_eq(other) || *self.data == *other.data
+    }
+}
+
+impl Eq for PureToken {}
+
+impl Hash for PureToken {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.data.hash(state)
+    }
+}
+
+impl PureChild {
+    pub fn text_range(&self) -> TextRange {
+        TextRange::at(self.offset, self.kind.text_len())
+    }
+}
+
 impl PureChildKind {
-    pub fn text_len(&self) -> usize {
+    pub fn text_len(&self) -> TextSize {
         match self {
             PureChildKind::Tree(it) => it.text_len(),
             PureChildKind::Token(it) => it.text_len(),
         }
     }
-    pub fn kind(&self) -> &'static str {
+    pub fn kind(&self) -> RawKind {
         match self {
             PureChildKind::Tree(it) => it.kind(),
             PureChildKind::Token(it) => it.kind(),
@@ -48,87 +145,297 @@ impl PureChildKind {
 }
 
 impl PureToken {
-    pub fn new(kind: &'static str, text: impl Into<String>) -> PureToken {
-        let text = text.into();
-        PureToken { kind, text }
+    pub fn new(kind: impl Into<RawKind>, text: impl Into<String>) -> PureToken {
+        let kind = kind.into();
+        let text = text.into().into_boxed_str();
+        PureToken { data: Arc::new(PureTokenData { kind, text }) }
     }
-    pub fn kind(&self) -> &'static str {
-        self.kind
+    pub fn kind(&self) -> RawKind {
+        self.data.kind
     }
     pub fn text(&self) -> &str {
-        self.text.as_str()
+        &self.data.text
+    }
+    pub fn text_len(&self) -> TextSize {
+        TextSize::of(self.text())
+    }
+    /// Checks if both tokens share the same allocation.
+    pub fn ptr_eq(&self, other: &PureToken) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
     }
-    pub fn text_len(&self) -> usize {
-        self.text.len()
+    /// Identity of the underlying allocation.
+    pub(crate) fn id(&self) -> usize {
+        Arc::as_ptr(&self.data) as usize
     }
 }
 
 impl PureTree {
-    pub fn new(kind: &'static str) -> PureTreeData {
-        PureTreeData { kind: kind.into(), text_len: 0, children: Vec::new() }
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(kind: impl Into<RawKind>) -> PureTreeData {
+        PureTreeData { kind: kind.into(), children: Children::new() }
     }
-    pub fn kind(&self) -> &'static str {
+    pub fn kind(&self) -> RawKind {
         self.data.kind
     }
-    pub fn text_len(&self) -> usize {
-        self.data.text_len
+    /// Checks if both trees share the same allocation.
+    pub fn ptr_eq(&self, other: &PureTree) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
     }
-    pub fn children(&self) -> impl Iterator<Item = &PureChild> + '_ {
-        self.data.children.iter()
+    /// Identity of the underlying allocation.
+    pub(crate) fn id(&self) -> usize {
+        Arc::as_ptr(&self.data) as usize
     }
-    pub fn get_child(&self, index: usize) -> Option<&PureChild> {
+    pub fn text_len(&self) -> TextSize {
+        self.data.children.text_len()
+    }
+    pub fn children(&self) -> impl Iterator<Item = PureChild> + '_ {
+        self.data.children.iter_from(0)
+    }
+    pub fn children_count(&self) -> usize {
+        self.data.children.len()
+    }
+    pub fn get_child(&self, index: usize) -> Option<PureChild> {
         self.data.children.get(index)
     }
+    pub fn text(&self) -> String {
+        self.to_string()
+    }
+    pub fn preorder(&self) -> PurePreorder {
+        PurePreorder { inner: self.preorder_with_tokens() }
+    }
+    pub fn preorder_with_tokens(&self) -> PurePreorderWithTokens {
+        PurePreorderWithTokens {
+            stack: Vec::new(),
+            next: Some(WalkEvent::Enter(self.clone().into())),
+        }
+    }
+    /// All trees of this subtree, including this one, in preorder.
+    pub fn descendants(&self) -> impl Iterator<Item = PureTree> {
+        self.preorder().filter_map(|event| match event {
+            WalkEvent::Enter(it) => Some(it),
+            WalkEvent::Leave(_) => None,
+        })
+    }
+    /// All elements of this subtree, including this tree, in preorder.
+    pub fn descendants_with_tokens(&self) -> impl Iterator<Item = PureChildKind> {
+        self.preorder_with_tokens().filter_map(|event| match event {
+            WalkEvent::Enter(it) => Some(it),
+            WalkEvent::Leave(_) => None,
+        })
+    }
+    /// The child containing `offset`, found by binary search. Empty children
+    /// never contain an offset.
+    pub fn child_at_offset(&self, offset: TextSize) -> Option<(usize, PureChild)> {
+        let idx = self.data.children.partition_by_end(|end| end <= offset);
+        let child = self.get_child(idx)?;
+        if child.offset <= offset {
+            Some((idx, child))
+        } else {
+            None
+        }
+    }
+    /// Children intersecting `range`, not counting those which only touch
+    /// its ends, found by binary search.
+    pub fn children_in_range(
+        &self,
+        range: TextRange,
+    ) -> impl Iterator<Item = (usize, PureChild)> + '_ {
+        let indices = self.children_range(range);
+        self.data
+            .children
+            .iter_from(indices.start)
+            .take(indices.len())
+            .zip(indices)
+            .map(|(it, idx)| (idx, it))
+    }
+    pub(crate) fn children_range(&self, range: TextRange) -> Range<usize> {
+        let start = self.data.children.partition_by_end(|end| end <= range.start());
+        // A child starts before `range.end()` if the previous one ends before
+        // it.
+        let end = if range.end() > TextSize::default() {
+            let ending_before = self.data.children.partition_by_end(|end| end < range.end());
+            (ending_before + 1).min(self.children_count())
+        } else {
+            0
+        };
+        start..end.max(start)
+    }
+    /// Children whose range contains `offset`, including the end.
+    pub(crate) fn children_touching(
+        &self,
+        offset: TextSize,
+    ) -> impl Iterator<Item = (usize, PureChild)> + '_ {
+        let start = self.data.children.partition_by_end(|end| end < offset);
+        (start..)
+            .zip(self.data.children.iter_from(start))
+            .take_while(move |(_, it)| it.offset <= offset)
+    }
+    /// The first child whose range contains `range`.
+    pub(crate) fn child_covering(&self, range: TextRange) -> Option<(usize, PureChild)> {
+        let idx = self.data.children.partition_by_end(|end| end < range.end());
+        let child = self.get_child(idx)?;
+        if child.text_range().contains_range(range) {
+            Some((idx, child))
+        } else {
+            None
+        }
+    }
     pub fn remove_child(&self, index: usize) -> PureTree {
-        self.modify(index, |children| {
-            let old_child = children.remove(index);
-            Delta::Sub(old_child.kind.text_len())
+        self.modify(|children| {
+            children.remove(index);
         })
     }
     pub fn insert_child(&self, index: usize, child: PureChildKind) -> PureTree {
-        self.modify(index + 1, |children| {
-            let len = child.text_len();
-            let offset = children.get(0).map_or(0, |it| it.offset);
-            children.insert(index, PureChild { offset, kind: child });
-            Delta::Add(len)
-        })
+        self.modify(|children| children.insert(index, child))
     }
     pub fn replace_child(&self, index: usize, child: PureChildKind) -> PureTree {
-        self.modify(index + 1, |children| {
-            let new_len = child.text_len();
-            let old_child = mem::replace(&mut children[index].kind, child);
-            let old_len = old_child.text_len();
-            Delta::new(old_len, new_len)
+        self.modify(|children| {
+            children.replace(index, child);
         })
     }
-    fn modify(
+    /// Replaces children in `range` with `children`, like `Vec::splice`.
+    pub fn splice_children(
         &self,
-        index: usize,
-        op: impl FnOnce(&mut Vec<PureChild>) -> Delta<usize>,
+        range: Range<usize>,
+        children: impl IntoIterator<Item = PureChildKind>,
     ) -> PureTree {
-        let mut data = self.data.clone();
-        {
-            let data = Arc::make_mut(&mut data);
-            let delta = op(&mut data.children);
-            for child in &mut data.children[index..] {
-                child.offset += delta;
+        self.modify(|old| old.splice(range, children.into_iter()))
+    }
+    /// Checks that the offsets of children are the sums of the lengths of
+    /// the preceding ones, that text lengths add up, and that the storage of
+    /// children is well-formed, in this tree and all its subtrees.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut report = Report::default();
+        self.validate_into(&mut report);
+        report.finish()
+    }
+    pub(crate) fn validate_into(&self, report: &mut Report) {
+        let mut path = Vec::new();
+        self.validate_node(&path, report);
+        // Trees being checked, with the index of the next child to visit.
+        let mut stack = vec![(self.clone(), 0)];
+        while let Some((tree, index)) = stack.last_mut() {
+            let child = match tree.get_child(*index) {
+                Some(it) => it,
+                None => {
+                    stack.pop();
+                    path.pop();
+                    continue;
+                }
+            };
+            path.push(*index);
+            *index += 1;
+            match child.kind {
+                PureChildKind::Tree(it) => {
+                    it.validate_node(&path, report);
+                    stack.push((it, 0));
+                }
+                PureChildKind::Token(_) => {
+                    path.pop();
+                }
             }
-            data.text_len += delta;
         }
+    }
+    fn validate_node(&self, path: &[usize], report: &mut Report) {
+        self.data.children.validate(path, report);
+        let len: TextSize = self.children().map(|it| it.kind.text_len()).sum();
+        if self.text_len() != len {
+            report.error(path, format!("length {:?}, expected {:?}", self.text_len(), len));
+        }
+    }
+    fn modify(&self, op: impl FnOnce(&mut Children)) -> PureTree {
+        let mut data = self.data.clone();
+        op(&mut Arc::make_mut(&mut data).children);
         PureTree { data }
     }
 }
 
 impl PureTreeData {
     pub fn push(mut self, child: impl Into<PureChildKind>) -> PureTreeData {
-        let kind = child.into();
-        let offset = self.text_len;
-        self.text_len += kind.text_len();
-        self.children.push(PureChild { offset, kind });
+        self.children.push(child.into());
         self
     }
 }
 
+pub struct PurePreorderWithTokens {
+    // Trees we are currently inside of, together with the index of the child
+    // being visited.
+    stack: Vec<(PureTree, usize)>,
+    next: Option<WalkEvent<PureChildKind>>,
+}
+
+impl PurePreorderWithTokens {
+    /// Skips the children of the tree which was just entered.
+    pub fn skip_subtree(&mut self) {
+        if let Some(WalkEvent::Enter(_)) = &self.next {
+            if let Some((tree, _)) = self.stack.pop() {
+                self.next = Some(WalkEvent::Leave(tree.into()));
+            }
+        }
+    }
+}
+
+impl Iterator for PurePreorderWithTokens {
+    type Item = WalkEvent<PureChildKind>;
+
+    fn next(&mut self) -> Option<WalkEvent<PureChildKind>> {
+        let next = self.next.take()?;
+        self.next = match &next {
+            WalkEvent::Enter(PureChildKind::Tree(tree)) => match tree.get_child(0) {
+                Some(child) => {
+                    self.stack.push((tree.clone(), 0));
+                    Some(WalkEvent::Enter(child.kind.clone()))
+                }
+                None => Some(WalkEvent::Leave(tree.clone().into())),
+            },
+            WalkEvent::Enter(PureChildKind::Token(token)) => {
+                Some(WalkEvent::Leave(token.clone().into()))
+            }
+            WalkEvent::Leave(_) => match self.stack.last_mut() {
+                Some((parent, idx)) => {
+                    *idx += 1;
+                    match parent.get_child(*idx) {
+                        Some(child) => Some(WalkEvent::Enter(child.kind.clone())),
+                        None => {
+                            let (parent, _) = self.stack.pop().unwrap();
+                            Some(WalkEvent::Leave(parent.into()))
+                        }
+                    }
+                }
+                None => None,
+            },
+        };
+        Some(next)
+    }
+}
+
+pub struct PurePreorder {
+    inner: PurePreorderWithTokens,
+}
+
+impl PurePreorder {
+    /// Skips the children of the tree which was just entered.
+    pub fn skip_subtree(&mut self) {
+        self.inner.skip_subtree()
+    }
+}
+
+impl Iterator for PurePreorder {
+    type Item = WalkEvent<PureTree>;
+
+    fn next(&mut self) -> Option<WalkEvent<PureTree>> {
+        loop {
+            let event = match self.inner.next()? {
+                WalkEvent::Enter(PureChildKind::Tree(it)) => WalkEvent::Enter(it),
+                WalkEvent::Leave(PureChildKind::Tree(it)) => WalkEvent::Leave(it),
+                WalkEvent::Enter(PureChildKind::Token(_))
+                | WalkEvent::Leave(PureChildKind::Token(_)) => continue,
+            };
+            return Some(event);
+        }
+    }
+}
+
 impl From<PureTreeData> for PureTree {
     fn from(data: PureTreeData) -> PureTree {
         PureTree { data: Arc::new(data) }
@@ -153,30 +460,124 @@ impl From<PureTree> for PureChildKind {
     }
 }
 
+/// Kinds are printed as `RawKind`s, as a pure tree doesn't know its
+/// language. Use `SyntaxTree<L>` to print them as `L::Kind`.
 impl fmt::Debug for PureTree {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.debug_fmt(f, &fmt_raw_kind)
+    }
+}
+
+impl fmt::Display for PureTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.preorder_with_tokens().try_for_each(|event| match event {
+            WalkEvent::Enter(PureChildKind::Token(it)) => f.write_str(it.text()),
+            _ => Ok(()),
+        })
+    }
+}
+
+/// Prints the kind like the `Debug` impl of `PureTree`.
+impl fmt::Debug for PureToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.debug_fmt(f, &fmt_raw_kind)
+    }
+}
+
+impl fmt::Display for PureToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.text())
+    }
+}
+
+impl fmt::Display for PureChildKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PureChildKind::Tree(it) => fmt::Display::fmt(it, f),
+            PureChildKind::Token(it) => fmt::Display::fmt(it, f),
+        }
+    }
+}
+
+fn fmt_raw_kind(kind: RawKind, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    // Drops the alternate flag, which would spread the kind over lines.
+    write!(f, "{:?}", kind)
+}
+
+/// Formats a raw kind, so that typed layers can print their own kinds.
+pub(crate) type KindFmt<'a> = &'a dyn Fn(RawKind, &mut fmt::Formatter<'_>) -> fmt::Result;
+
+impl PureTree {
+    pub(crate) fn debug_fmt(&self, f: &mut fmt::Formatter<'_>, kind: KindFmt) -> fmt::Result {
         if f.alternate() {
-            fmt_rec(f, 0, self)
+            fmt_tree(f, self, kind)
         } else {
-            write!(f, "{}", self.kind())
+            kind(self.kind(), f)
         }
     }
 }
-impl fmt::Debug for PureToken {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}: {}", self.text, self.kind)
+
+impl PureToken {
+    pub(crate) fn debug_fmt(&self, f: &mut fmt::Formatter<'_>, kind: KindFmt) -> fmt::Result {
+        write!(f, "{:?}: ", self.text())?;
+        kind(self.kind(), f)
     }
 }
 
-fn fmt_rec(f: &mut fmt::Formatter<'_>, lvl: usize, tree: &PureTree) -> fmt::Result {
-    writeln!(f, "{:indent$}{}", "", tree.kind(), indent = lvl * 2)?;
-    for child in tree.children() {
-        match &child.kind {
-            PureChildKind::Tree(it) => fmt_rec(f, lvl + 1, &it)?,
-            PureChildKind::Token(it) => {
-                writeln!(f, "{:indent$}{:?}", "", it, indent = lvl * 2 + 2)?
+fn fmt_tree(f: &mut fmt::Formatter<'_>, tree: &PureTree, kind: KindFmt) -> fmt::Result {
+    let mut lvl = 0;
+    for event in tree.preorder_with_tokens() {
+        match event {
+            WalkEvent::Enter(PureChildKind::Tree(it)) => {
+                write!(f, "{:indent$}", "", indent = lvl * 2)?;
+                kind(it.kind(), f)?;
+                writeln!(f)?;
+                lvl += 1;
             }
+            WalkEvent::Enter(PureChildKind::Token(it)) => {
+                write!(f, "{:indent$}", "", indent = lvl * 2)?;
+                it.debug_fmt(f, kind)?;
+                writeln!(f)?;
+            }
+            WalkEvent::Leave(PureChildKind::Tree(_)) => lvl -= 1,
+            WalkEvent::Leave(PureChildKind::Token(_)) => (),
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_children_of_different_kinds() {
+        let wide =
+            (0..100).fold(PureTree::new("list"), |tree, _| tree.push(PureToken::new("x", "x")));
+        let list = PureTree::from(wide);
+        let tuple = PureTree {
+            data: Arc::new(PureTreeData {
+                kind: "tuple".into(),
+                children: list.data.children.clone(),
+            }),
+        };
+        assert!(list.data.children.shares_storage(&tuple.data.children));
+        assert_ne!(list, tuple);
+        assert_eq!(list, PureTree { data: Arc::new((*list.data).clone()) });
+    }
+
+    #[test]
+    fn validate_offsets() {
+        let children = Children::Small(vec![
+            PureChild { offset: 0.into(), kind: PureToken::new("x", "ab").into() },
+            PureChild { offset: 3.into(), kind: PureToken::new("x", "ab").into() },
+        ]);
+        let inner = PureTree { data: Arc::new(PureTreeData { kind: "inner".into(), children }) };
+        let tree = PureTree::from(PureTree::new("root").push(PureToken::new("x", "a")).push(inner));
+        let err = tree.validate().unwrap_err();
+        assert_eq!(
+            err.errors(),
+            ["at [1]: child 1 at 3, expected 2", "at [1]: length 5, expected 4"]
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use std::{mem, ops::Range};
+
+use crate::{
+    shared::{Rc, RefCell, SendSync, Weak},
+    Language, PureTree, SyntaxText, SyntaxTree, TextRange, TextSize,
+};
+
+/// Replacement of `range` with `new_text`, as understood by editors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: TextRange,
+    pub new_text: String,
+}
+
+/// Gets notified about every change to the text of an observed tree.
+pub(crate) trait Observer: SendSync {
+    /// `range` is relative to the observed tree, before the change. `new` is
+    /// the observed tree after the change.
+    fn on_change(&self, range: TextRange, new_len: TextSize, new: &PureTree);
+}
+
+/// Records mutations of a `SyntaxTree` as text edits.
+///
+/// Edits are relative to the start of the observed tree and refer to its
+/// text as of the moment recording started (or the last `drain`). They are
+/// sorted and disjoint, and adjacent edits are merged. Recording stops when
+/// the recorder is dropped.
+pub struct MutationRecorder {
+    recording: Rc<Recording>,
+}
+
+struct Recording {
+    edits: RefCell<Vec<TextEdit>>,
+}
+
+impl MutationRecorder {
+    pub fn new<L: Language>(tree: &SyntaxTree<L>) -> MutationRecorder {
+        let recording = Rc::new(Recording { edits: Default::default() });
+        let observer: Weak<dyn Observer> = Rc::downgrade(&recording) as Weak<Recording>;
+        tree.add_observer(observer);
+        MutationRecorder { recording }
+    }
+
+    /// Returns the edits recorded so far and starts afresh from the current
+    /// text.
+    pub fn drain(&self) -> Vec<TextEdit> {
+        mem::take(&mut *self.recording.edits.borrow_mut())
+    }
+}
+
+impl Observer for Recording {
+    fn on_change(&self, range: TextRange, new_len: TextSize, new: &PureTree) {
+        let mut edits = self.edits.borrow_mut();
+        // Edits in `merged` overlap or touch the change and are folded into
+        // a single edit, which spans `start..end` in the current text.
+        let mut merged = 0..0;
+        let (mut start, mut end) = (range.start(), range.end());
+        let (mut shift_before, mut shift_after) = (0, 0);
+        for (idx, edit) in edits.iter().enumerate() {
+            let current = TextRange::at(
+                shifted(edit.range.start(), shift_after),
+                TextSize::of(&edit.new_text),
+            );
+            if current.start() > end {
+                break;
+            }
+            let delta = len_delta(edit.range.len(), current.len());
+            shift_after += delta;
+            if current.end() < start {
+                shift_before += delta;
+                merged = idx + 1..idx + 1;
+                continue;
+            }
+            merged.end = idx + 1;
+            start = start.min(current.start());
+            end = end.max(current.end());
+        }
+
+        let new_end = shifted(end, len_delta(range.len(), new_len));
+        let edit = TextEdit {
+            range: TextRange::new(shifted(start, -shift_before), shifted(end, -shift_after)),
+            new_text: SyntaxText::new(new.clone()).slice(start..new_end).to_string(),
+        };
+        if edit.range.is_empty() && edit.new_text.is_empty() {
+            edits.drain(merged);
+        } else {
+            edits.splice(merged, Some(edit));
+        }
+    }
+}
+
+fn len_delta(old: TextSize, new: TextSize) -> i64 {
+    i64::from(u32::from(new)) - i64::from(u32::from(old))
+}
+
+fn shifted(offset: TextSize, by: i64) -> TextSize {
+    TextSize::from((i64::from(u32::from(offset)) + by) as u32)
+}
+
+/// The range of `old` that was replaced to get `new`, and the length of the
+/// replacement. Children in `replaced` were replaced by `new_count` children,
+/// unchanged ones at both ends are excluded.
+pub(crate) fn changed_range(
+    old: &PureTree,
+    new: &PureTree,
+    replaced: Range<usize>,
+    new_count: usize,
+) -> (TextRange, TextSize) {
+    let same = |old_idx: usize, new_idx: usize| {
+        old.get_child(old_idx).unwrap().kind == new.get_child(new_idx).unwrap().kind
+    };
+    let start = replaced.start;
+    let common = replaced.len().min(new_count);
+    let prefix = (0..common).take_while(|&n| same(start + n, start + n)).count();
+    let suffix = (1..=common - prefix)
+        .take_while(|&n| same(replaced.end - n, start + new_count - n))
+        .count();
+
+    let offset = |tree: &PureTree, index: usize| {
+        tree.get_child(index).map_or(tree.text_len(), |it| it.offset)
+    };
+    let start_offset = offset(old, start + prefix);
+    let old_end = offset(old, replaced.end - suffix);
+    let new_end = offset(new, start + new_count - suffix);
+    (TextRange::new(start_offset, old_end), new_end - start_offset)
+}
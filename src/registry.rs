@@ -0,0 +1,368 @@
+//! Ordered Registry
+//!
+//! A treap of values keyed by index, where each node stores its key relative
+//! to its parent. Shifting all keys from some point on only touches a single
+//! root-to-leaf path, and computing the key of a node walks up to the root,
+//! so everything is logarithmic in the number of values.
+//!
+//! Values are addressed by stable slots, which stay valid until removal.
+
+use std::ops::Range;
+
+const NIL: usize = usize::MAX;
+
+pub(crate) struct Registry<T> {
+    nodes: Vec<Node<T>>,
+    free: Vec<usize>,
+    root: usize,
+    seed: u64,
+}
+
+struct Node<T> {
+    value: Option<T>,
+    // Relative to the parent's key, or absolute for the root.
+    key: isize,
+    priority: u64,
+    parent: usize,
+    left: usize,
+    right: usize,
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Registry { nodes: Vec::new(), free: Vec::new(), root: NIL, seed: 0x2545_f491_4f6c_dd1d }
+    }
+}
+
+impl<T> Registry<T> {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.root == NIL
+    }
+
+    pub(crate) fn get(&self, key: usize) -> Option<&T> {
+        let key = key as isize;
+        let (mut curr, mut base) = (self.root, 0);
+        while curr != NIL {
+            let node = &self.nodes[curr];
+            let curr_key = base + node.key;
+            if key == curr_key {
+                return node.value.as_ref();
+            }
+            curr = if key < curr_key { node.left } else { node.right };
+            base = curr_key;
+        }
+        None
+    }
+
+    pub(crate) fn key(&self, slot: usize) -> usize {
+        self.try_key(slot).expect("free slot")
+    }
+    /// The key of `slot`, or `None` if the slot is free.
+    pub(crate) fn try_key(&self, slot: usize) -> Option<usize> {
+        self.nodes.get(slot)?.value.as_ref()?;
+        let mut key = 0;
+        let mut curr = slot;
+        while curr != NIL {
+            key += self.nodes[curr].key;
+            curr = self.nodes[curr].parent;
+        }
+        Some(key as usize)
+    }
+
+    /// Adds `value` under `key`, which must be vacant, and returns its slot.
+    pub(crate) fn insert(&mut self, key: usize, value: T) -> usize {
+        let key = key as isize;
+        let slot = self.alloc(value);
+        if self.root == NIL {
+            self.nodes[slot].key = key;
+            self.root = slot;
+            return slot;
+        }
+
+        let (mut curr, mut base) = (self.root, 0);
+        loop {
+            let curr_key = base + self.nodes[curr].key;
+            assert!(key != curr_key, "duplicate key: {}", key);
+            let next = if key < curr_key { self.nodes[curr].left } else { self.nodes[curr].right };
+            if next == NIL {
+                if key < curr_key {
+                    self.nodes[curr].left = slot;
+                } else {
+                    self.nodes[curr].right = slot;
+                }
+                self.nodes[slot].parent = curr;
+                self.nodes[slot].key = key - curr_key;
+                break;
+            }
+            curr = next;
+            base = curr_key;
+        }
+
+        loop {
+            let parent = self.nodes[slot].parent;
+            if parent == NIL || self.nodes[parent].priority >= self.nodes[slot].priority {
+                break;
+            }
+            self.rotate_up(slot);
+        }
+        slot
+    }
+
+    pub(crate) fn remove(&mut self, slot: usize) -> T {
+        loop {
+            let Node { left, right, .. } = self.nodes[slot];
+            let child = match (left, right) {
+                (NIL, NIL) => break,
+                (NIL, it) | (it, NIL) => it,
+                _ if self.nodes[left].priority > self.nodes[right].priority => left,
+                _ => right,
+            };
+            self.rotate_up(child);
+        }
+        let parent = self.nodes[slot].parent;
+        self.replace_child(parent, slot, NIL);
+        self.free.push(slot);
+        self.nodes[slot].value.take().unwrap()
+    }
+
+    /// Adds `by` to all keys which are at least `from`. Keys must stay
+    /// distinct and ordered.
+    pub(crate) fn shift(&mut self, from: usize, by: isize) {
+        let from = from as isize;
+        let (mut curr, mut base) = (self.root, 0);
+        while curr != NIL {
+            let key = base + self.nodes[curr].key;
+            if key >= from {
+                // Moves the whole subtree, the left part is moved back.
+                self.nodes[curr].key += by;
+                let left = self.nodes[curr].left;
+                if left != NIL {
+                    self.nodes[left].key -= by;
+                }
+                curr = left;
+                base = key + by;
+            } else {
+                curr = self.nodes[curr].right;
+                base = key;
+            }
+        }
+    }
+
+    /// Keys and values with keys in `range`, in order.
+    pub(crate) fn in_range(&self, range: Range<usize>) -> Vec<(usize, &T)> {
+        let mut res = Vec::new();
+        self.collect(self.root, 0, &range, &mut res);
+        res
+    }
+
+    /// Describes the broken invariants of the treap: ordering of keys, heap
+    /// order of priorities, parent links and bookkeeping of free slots.
+    pub(crate) fn check(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        if self.root != NIL && self.nodes[self.root].parent != NIL {
+            res.push(format!("root {} has a parent", self.root));
+        }
+        let mut reachable = 0;
+        let mut prev_key = None;
+        // In-order traversal, with the keys of the nodes on the stack.
+        let mut stack = Vec::new();
+        let (mut curr, mut base) = (self.root, 0);
+        loop {
+            while curr != NIL && reachable + stack.len() <= self.nodes.len() {
+                let key = base + self.nodes[curr].key;
+                stack.push((curr, key));
+                curr = self.nodes[curr].left;
+                base = key;
+            }
+            let (slot, key) = match stack.pop() {
+                Some(it) => it,
+                None => break,
+            };
+            reachable += 1;
+            let node = &self.nodes[slot];
+            if node.value.is_none() {
+                res.push(format!("slot {} is both free and in use", slot));
+            }
+            if prev_key.is_some_and(|prev| prev >= key) {
+                res.push(format!("key {} of slot {} is out of order", key, slot));
+            }
+            prev_key = Some(key);
+            for child in [node.left, node.right] {
+                if child == NIL {
+                    continue;
+                }
+                if self.nodes[child].parent != slot {
+                    res.push(format!("slot {} doesn't link back to its parent {}", child, slot));
+                }
+                if self.nodes[child].priority > node.priority {
+                    res.push(format!("slot {} has a higher priority than its parent", child));
+                }
+            }
+            curr = node.right;
+            base = key;
+        }
+        let used = self.nodes.len() - self.free.len();
+        if reachable != used {
+            res.push(format!("{} slots in use, but {} reachable", used, reachable));
+        }
+        res
+    }
+
+    fn collect<'a>(
+        &'a self,
+        node: usize,
+        base: isize,
+        range: &Range<usize>,
+        res: &mut Vec<(usize, &'a T)>,
+    ) {
+        if node == NIL {
+            return;
+        }
+        let node = &self.nodes[node];
+        let key = (base + node.key) as usize;
+        if range.start < key {
+            self.collect(node.left, key as isize, range, res);
+        }
+        if range.contains(&key) {
+            res.push((key, node.value.as_ref().unwrap()));
+        }
+        if key < range.end {
+            self.collect(node.right, key as isize, range, res);
+        }
+    }
+
+    fn alloc(&mut self, value: T) -> usize {
+        // xorshift64
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        let node = Node {
+            value: Some(value),
+            key: 0,
+            priority: self.seed,
+            parent: NIL,
+            left: NIL,
+            right: NIL,
+        };
+        match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    /// Makes `node` the parent of its current parent.
+    fn rotate_up(&mut self, node: usize) {
+        let parent = self.nodes[node].parent;
+        let grandparent = self.nodes[parent].parent;
+        let key = self.nodes[node].key;
+        let inner = if self.nodes[parent].left == node {
+            let inner = self.nodes[node].right;
+            self.nodes[parent].left = inner;
+            self.nodes[node].right = parent;
+            inner
+        } else {
+            let inner = self.nodes[node].left;
+            self.nodes[parent].right = inner;
+            self.nodes[node].left = parent;
+            inner
+        };
+        if inner != NIL {
+            self.nodes[inner].parent = parent;
+            self.nodes[inner].key += key;
+        }
+        self.nodes[node].key += self.nodes[parent].key;
+        self.nodes[parent].key = -key;
+        self.nodes[parent].parent = node;
+        self.nodes[node].parent = grandparent;
+        self.replace_child(grandparent, parent, node);
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        if parent == NIL {
+            self.root = new;
+        } else if self.nodes[parent].left == old {
+            self.nodes[parent].left = new;
+        } else {
+            self.nodes[parent].right = new;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> Registry<char> {
+        let mut res = Registry::default();
+        for (key, value) in "abcdefgh".chars().enumerate() {
+            res.insert(key * 10, value);
+        }
+        res.remove(3);
+        assert!(res.check().is_empty());
+        res
+    }
+
+    /// A slot with a parent and a child.
+    fn middle(registry: &Registry<char>) -> usize {
+        let root = &registry.nodes[registry.root];
+        IntoIterator::into_iter([root.left, root.right])
+            .find(|&it| it != NIL && registry.nodes[it].left != NIL)
+            .unwrap()
+    }
+
+    #[test]
+    fn check_order() {
+        let mut registry = registry();
+        // Moves the leaf with key 0 past all other keys, so the next key is
+        // the first one out of order.
+        let slot = (0..registry.nodes.len()).find(|&it| registry.try_key(it) == Some(0)).unwrap();
+        let next = (0..registry.nodes.len()).find(|&it| registry.try_key(it) == Some(10)).unwrap();
+        assert_eq!((registry.nodes[slot].left, registry.nodes[slot].right), (NIL, NIL));
+        registry.nodes[slot].key += 1000;
+        let message = format!("key 10 of slot {} is out of order", next);
+        assert_eq!(registry.check(), vec![message]);
+    }
+
+    #[test]
+    fn check_links() {
+        let mut registry = registry();
+        let slot = middle(&registry);
+        let left = registry.nodes[slot].left;
+        registry.nodes[left].parent = registry.root;
+        let message = format!("slot {} doesn't link back to its parent {}", left, slot);
+        assert_eq!(registry.check(), vec![message]);
+
+        let mut registry = self::registry();
+        let root = registry.root;
+        registry.nodes[root].parent = slot;
+        assert_eq!(registry.check(), vec![format!("root {} has a parent", root)]);
+    }
+
+    #[test]
+    fn check_priorities() {
+        let mut registry = registry();
+        let slot = middle(&registry);
+        registry.nodes[slot].priority = u64::MAX;
+        let message = format!("slot {} has a higher priority than its parent", slot);
+        assert_eq!(registry.check(), vec![message]);
+    }
+
+    #[test]
+    fn check_free_slots() {
+        let mut registry = registry();
+        let slot = middle(&registry);
+        registry.free.push(slot);
+        assert_eq!(registry.check(), vec!["6 slots in use, but 7 reachable"]);
+
+        let mut registry = self::registry();
+        registry.nodes[slot].value = None;
+        assert_eq!(registry.check(), vec![format!("slot {} is both free and in use", slot)]);
+        assert_eq!(registry.try_key(slot), None);
+    }
+}
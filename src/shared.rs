@@ -0,0 +1,212 @@
+//! Reference counting and interior mutability for the mutable tree.
+//!
+//! By default these are the single-threaded `std` types. With the `sync`
+//! feature they are replaced by `Arc` and lock-based cells with the same
+//! interface, and access to each tree is serialized by a reentrant lock
+//! shared by its elements.
+
+#[cfg(not(feature = "sync"))]
+pub(crate) use self::unsync::*;
+
+#[cfg(feature = "sync")]
+pub(crate) use self::sync::*;
+
+#[cfg(not(feature = "sync"))]
+mod unsync {
+    pub(crate) use std::{
+        cell::RefCell,
+        rc::{Rc, Weak},
+    };
+
+    /// `std::cell::Cell` which can also clone its contents.
+    #[derive(Default)]
+    pub(crate) struct Cell<T>(std::cell::Cell<T>);
+
+    impl<T> Cell<T> {
+        pub(crate) fn new(value: T) -> Cell<T> {
+            Cell(std::cell::Cell::new(value))
+        }
+        pub(crate) fn set(&self, value: T) {
+            self.0.set(value)
+        }
+        pub(crate) fn get(&self) -> T
+        where
+            T: Copy,
+        {
+            self.0.get()
+        }
+        pub(crate) fn take(&self) -> T
+        where
+            T: Default,
+        {
+            self.0.take()
+        }
+        pub(crate) fn cloned(&self) -> T
+        where
+            T: Clone + Default,
+        {
+            let value = self.0.take();
+            self.0.set(value.clone());
+            value
+        }
+    }
+
+    pub(crate) trait SendSync {}
+
+    impl<T: ?Sized> SendSync for T {}
+
+    #[derive(Clone, Default)]
+    pub(crate) struct TreeLock(());
+
+    impl TreeLock {
+        pub(crate) fn acquire(&self) {}
+        pub(crate) fn release(&self) {}
+    }
+
+    pub(crate) struct TreeGuard;
+
+    pub(crate) fn lock_all(_locks: impl Fn() -> Vec<TreeLock>) -> TreeGuard {
+        TreeGuard
+    }
+}
+
+#[cfg(feature = "sync")]
+mod sync {
+    use std::{
+        mem,
+        sync::{
+            Condvar, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+        },
+        thread::{self, ThreadId},
+    };
+
+    pub(crate) use std::sync::{Arc as Rc, Weak};
+
+    #[derive(Default)]
+    pub(crate) struct Cell<T>(Mutex<T>);
+
+    impl<T> Cell<T> {
+        pub(crate) fn new(value: T) -> Cell<T> {
+            Cell(Mutex::new(value))
+        }
+        pub(crate) fn set(&self, value: T) {
+            drop(self.replace(value))
+        }
+        pub(crate) fn get(&self) -> T
+        where
+            T: Copy,
+        {
+            *self.lock()
+        }
+        pub(crate) fn take(&self) -> T
+        where
+            T: Default,
+        {
+            self.replace(T::default())
+        }
+        pub(crate) fn cloned(&self) -> T
+        where
+            T: Clone,
+        {
+            self.lock().clone()
+        }
+        fn replace(&self, value: T) -> T {
+            mem::replace(&mut *self.lock(), value)
+        }
+        fn lock(&self) -> MutexGuard<'_, T> {
+            self.0.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+
+    #[derive(Default)]
+    pub(crate) struct RefCell<T>(RwLock<T>);
+
+    impl<T> RefCell<T> {
+        pub(crate) fn new(value: T) -> RefCell<T> {
+            RefCell(RwLock::new(value))
+        }
+        pub(crate) fn borrow(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().unwrap_or_else(PoisonError::into_inner)
+        }
+        pub(crate) fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+
+    pub(crate) trait SendSync: Send + Sync {}
+
+    impl<T: ?Sized + Send + Sync> SendSync for T {}
+
+    /// A reentrant lock, shared by all elements of a tree to serialize access
+    /// to it.
+    #[derive(Clone, Default)]
+    pub(crate) struct TreeLock(Rc<LockState>);
+
+    #[derive(Default)]
+    struct LockState {
+        // The thread holding the lock and how many times it has acquired it.
+        owner: Mutex<(Option<ThreadId>, usize)>,
+        released: Condvar,
+    }
+
+    impl TreeLock {
+        pub(crate) fn acquire(&self) {
+            let me = thread::current().id();
+            let mut owner = self.0.owner.lock().unwrap_or_else(PoisonError::into_inner);
+            loop {
+                match owner.0 {
+                    None => *owner = (Some(me), 1),
+                    Some(it) if it == me => owner.1 += 1,
+                    Some(_) => {
+                        owner = self.0.released.wait(owner).unwrap_or_else(PoisonError::into_inner);
+                        continue;
+                    }
+                }
+                return;
+            }
+        }
+        pub(crate) fn release(&self) {
+            let mut owner = self.0.owner.lock().unwrap_or_else(PoisonError::into_inner);
+            owner.1 -= 1;
+            if owner.1 == 0 {
+                owner.0 = None;
+                self.0.released.notify_one();
+            }
+        }
+        fn id(&self) -> usize {
+            Rc::as_ptr(&self.0) as usize
+        }
+    }
+
+    /// Locks acquired by `lock_all`, released on drop.
+    pub(crate) struct TreeGuard(Vec<TreeLock>);
+
+    impl Drop for TreeGuard {
+        fn drop(&mut self) {
+            for lock in &self.0 {
+                lock.release()
+            }
+        }
+    }
+
+    /// Acquires the current `locks`, which may change until they are held.
+    ///
+    /// An element only switches locks while holding both the old and the new
+    /// one, so the locks are looked up again once acquired.
+    pub(crate) fn lock_all(locks: impl Fn() -> Vec<TreeLock>) -> TreeGuard {
+        loop {
+            let mut held = locks();
+            // Locking in a fixed order prevents deadlocks.
+            held.sort_by_key(TreeLock::id);
+            held.dedup_by_key(|it| it.id());
+            for lock in &held {
+                lock.acquire();
+            }
+            let guard = TreeGuard(held);
+            if locks().iter().all(|it| guard.0.binary_search_by_key(&it.id(), TreeLock::id).is_ok())
+            {
+                return guard;
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    convert::Infallible,
+    fmt,
+    ops::{Bound, RangeBounds},
+};
+
+use crate::{PureChildKind, PureToken, PureTree, TextRange, TextSize};
+
+/// A view of the text covered by a tree.
+///
+/// `SyntaxText` holds on to the immutable `PureTree` it was created from, and
+/// walks its tokens on demand instead of materializing a `String`.
+#[derive(Clone)]
+pub struct SyntaxText {
+    tree: PureTree,
+    range: TextRange,
+}
+
+impl SyntaxText {
+    pub(crate) fn new(tree: PureTree) -> SyntaxText {
+        let range = TextRange::up_to(tree.text_len());
+        SyntaxText { tree, range }
+    }
+
+    pub fn len(&self) -> TextSize {
+        self.range.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty()
+    }
+
+    pub fn contains_char(&self, c: char) -> bool {
+        self.try_for_each_chunk(|chunk| if chunk.contains(c) { Err(()) } else { Ok(()) }).is_err()
+    }
+
+    pub fn find_char(&self, c: char) -> Option<TextSize> {
+        let mut acc = TextSize::default();
+        let res = self.try_for_each_chunk(|chunk| {
+            if let Some(pos) = chunk.find(c) {
+                return Err(acc + TextSize::of(&chunk[..pos]));
+            }
+            acc += TextSize::of(chunk);
+            Ok(())
+        });
+        res.err()
+    }
+
+    pub fn char_at(&self, offset: TextSize) -> Option<char> {
+        let mut start = TextSize::default();
+        let res = self.try_for_each_chunk(|chunk| {
+            let end = start + TextSize::of(chunk);
+            if start <= offset && offset < end {
+                let pos = usize::from(offset - start);
+                return Err(chunk.get(pos..).and_then(|it| it.chars().next()));
+            }
+            start = end;
+            Ok(())
+        });
+        res.err().flatten()
+    }
+
+    pub fn slice(&self, range: impl RangeBounds<TextSize>) -> SyntaxText {
+        let one = TextSize::from(1);
+        let start = match range.start_bound() {
+            Bound::Included(&it) => it,
+            Bound::Excluded(&it) => it + one,
+            Bound::Unbounded => TextSize::default(),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&it) => it + one,
+            Bound::Excluded(&it) => it,
+            Bound::Unbounded => self.len(),
+        };
+        assert!(start <= end, "invalid slice, range: {:?}..{:?}", start, end);
+        assert!(
+            end <= self.len(),
+            "invalid slice, range: {:?}..{:?}, len: {:?}",
+            start,
+            end,
+            self.len()
+        );
+        let range = TextRange::new(start, end) + self.range.start();
+        SyntaxText { tree: self.tree.clone(), range }
+    }
+
+    pub fn try_fold_chunks<T, F, E>(&self, init: T, mut f: F) -> Result<T, E>
+    where
+        F: FnMut(T, &str) -> Result<T, E>,
+    {
+        let mut acc = init;
+        for (token, range) in self.chunks() {
+            acc = f(acc, &token.text()[range])?;
+        }
+        Ok(acc)
+    }
+
+    fn chunks(&self) -> Chunks {
+        let mut res = Chunks { range: self.range, stack: Vec::new() };
+        res.push(self.tree.clone(), TextSize::default());
+        res
+    }
+
+    pub fn try_for_each_chunk<F: FnMut(&str) -> Result<(), E>, E>(
+        &self,
+        mut f: F,
+    ) -> Result<(), E> {
+        self.try_fold_chunks((), move |(), chunk| f(chunk))
+    }
+
+    pub fn for_each_chunk<F: FnMut(&str)>(&self, mut f: F) {
+        let res = self.try_for_each_chunk(|chunk| {
+            f(chunk);
+            Ok::<(), Infallible>(())
+        });
+        match res {
+            Ok(()) => (),
+            Err(void) => match void {},
+        }
+    }
+}
+
+impl fmt::Debug for SyntaxText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_string(), f)
+    }
+}
+
+impl fmt::Display for SyntaxText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.try_for_each_chunk(|chunk| fmt::Display::fmt(chunk, f))
+    }
+}
+
+impl From<SyntaxText> for String {
+    fn from(text: SyntaxText) -> String {
+        text.to_string()
+    }
+}
+
+impl PartialEq<str> for SyntaxText {
+    fn eq(&self, mut rhs: &str) -> bool {
+        self.try_for_each_chunk(|chunk| {
+            if !rhs.starts_with(chunk) {
+                return Err(());
+            }
+            rhs = &rhs[chunk.len()..];
+            Ok(())
+        })
+        .is_ok()
+            && rhs.is_empty()
+    }
+}
+
+impl PartialEq<SyntaxText> for str {
+    fn eq(&self, rhs: &SyntaxText) -> bool {
+        rhs == self
+    }
+}
+
+impl PartialEq<&'_ str> for SyntaxText {
+    fn eq(&self, rhs: &&str) -> bool {
+        self == *rhs
+    }
+}
+
+impl PartialEq<SyntaxText> for &'_ str {
+    fn eq(&self, rhs: &SyntaxText) -> bool {
+        rhs == *self
+    }
+}
+
+impl PartialEq<String> for SyntaxText {
+    fn eq(&self, rhs: &String) -> bool {
+        self == rhs.as_str()
+    }
+}
+
+impl PartialEq<SyntaxText> for String {
+    fn eq(&self, rhs: &SyntaxText) -> bool {
+        rhs == self.as_str()
+    }
+}
+
+impl PartialEq for SyntaxText {
+    fn eq(&self, other: &SyntaxText) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+        // Walks the chunks of `other` in lockstep. Bytes are compared, as
+        // chunks of equal texts may end in the middle of different chars.
+        let mut rhs = other.chunks();
+        let (mut token, mut rest) = (None::<PureToken>, 0..0);
+        self.try_for_each_chunk(|chunk| {
+            let mut chunk = chunk.as_bytes();
+            while !chunk.is_empty() {
+                let text = match &token {
+                    Some(it) if !rest.is_empty() => &it.text().as_bytes()[rest.clone()],
+                    _ => {
+                        let (next, range) = rhs.next().ok_or(())?;
+                        token = Some(next);
+                        rest = usize::from(range.start())..usize::from(range.end());
+                        continue;
+                    }
+                };
+                let len = chunk.len().min(text.len());
+                if chunk[..len] != text[..len] {
+                    return Err(());
+                }
+                chunk = &chunk[len..];
+                rest.start += len;
+            }
+            Ok(())
+        })
+        .is_ok()
+    }
+}
+
+impl Eq for SyntaxText {}
+
+/// Tokens overlapping the range of a `SyntaxText`, in order, with the
+/// overlapping ranges within the tokens.
+struct Chunks {
+    range: TextRange,
+    // Trees being walked, with their offsets and the indices of the next
+    // children to visit.
+    stack: Vec<(PureTree, TextSize, usize)>,
+}
+
+impl Chunks {
+    /// Starts walking `tree` at the first child ending after the start of the
+    /// range, found by binary search.
+    fn push(&mut self, tree: PureTree, offset: TextSize) {
+        let start = self.range.start().checked_sub(offset).unwrap_or_default();
+        let index = tree.children_range(TextRange::empty(start)).start;
+        self.stack.push((tree, offset, index));
+    }
+}
+
+impl Iterator for Chunks {
+    type Item = (PureToken, TextRange);
+
+    fn next(&mut self) -> Option<(PureToken, TextRange)> {
+        while let Some((tree, offset, index)) = self.stack.last_mut() {
+            let child = match tree.get_child(*index) {
+                Some(it) => it,
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            *index += 1;
+            let child_range = child.text_range() + *offset;
+            if child_range.end() <= self.range.start() {
+                continue;
+            }
+            if child_range.start() >= self.range.end() {
+                self.stack.pop();
+                continue;
+            }
+            match child.kind {
+                PureChildKind::Tree(it) => self.push(it, child_range.start()),
+                PureChildKind::Token(it) => {
+                    let local = self.range.intersect(child_range).unwrap() - child_range.start();
+                    return Some((it, local));
+                }
+            }
+        }
+        None
+    }
+}
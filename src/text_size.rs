@@ -0,0 +1,210 @@
+//! Offsets and ranges in the source text.
+
+use std::{
+    convert::TryFrom,
+    fmt, iter,
+    num::TryFromIntError,
+    ops::{Add, AddAssign, Bound, Index, RangeBounds, Sub, SubAssign},
+};
+
+/// A measure of text length, in UTF-8 code units (bytes).
+///
+/// Stored as `u32`: source files larger than 4GiB are not supported.
+#[derive(Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TextSize {
+    raw: u32,
+}
+
+impl TextSize {
+    pub const fn new(raw: u32) -> TextSize {
+        TextSize { raw }
+    }
+    pub fn of(text: &str) -> TextSize {
+        TextSize::try_from(text.len()).unwrap()
+    }
+    pub fn checked_add(self, rhs: TextSize) -> Option<TextSize> {
+        self.raw.checked_add(rhs.raw).map(TextSize::new)
+    }
+    pub fn checked_sub(self, rhs: TextSize) -> Option<TextSize> {
+        self.raw.checked_sub(rhs.raw).map(TextSize::new)
+    }
+}
+
+impl fmt::Debug for TextSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.raw, f)
+    }
+}
+
+impl fmt::Display for TextSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.raw, f)
+    }
+}
+
+impl From<u32> for TextSize {
+    fn from(raw: u32) -> TextSize {
+        TextSize::new(raw)
+    }
+}
+
+impl From<TextSize> for u32 {
+    fn from(size: TextSize) -> u32 {
+        size.raw
+    }
+}
+
+impl From<TextSize> for usize {
+    fn from(size: TextSize) -> usize {
+        size.raw as usize
+    }
+}
+
+impl TryFrom<usize> for TextSize {
+    type Error = TryFromIntError;
+    fn try_from(value: usize) -> Result<TextSize, TryFromIntError> {
+        u32::try_from(value).map(TextSize::new)
+    }
+}
+
+impl Add for TextSize {
+    type Output = TextSize;
+    fn add(self, rhs: TextSize) -> TextSize {
+        TextSize::new(self.raw + rhs.raw)
+    }
+}
+
+impl Sub for TextSize {
+    type Output = TextSize;
+    fn sub(self, rhs: TextSize) -> TextSize {
+        TextSize::new(self.raw - rhs.raw)
+    }
+}
+
+impl AddAssign for TextSize {
+    fn add_assign(&mut self, rhs: TextSize) {
+        self.raw += rhs.raw
+    }
+}
+
+impl SubAssign for TextSize {
+    fn sub_assign(&mut self, rhs: TextSize) {
+        self.raw -= rhs.raw
+    }
+}
+
+impl iter::Sum for TextSize {
+    fn sum<I: Iterator<Item = TextSize>>(iter: I) -> TextSize {
+        iter.fold(TextSize::default(), Add::add)
+    }
+}
+
+/// A range in the source text, `start..end`.
+///
+/// Invariant: `start <= end`.
+#[derive(Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TextRange {
+    start: TextSize,
+    end: TextSize,
+}
+
+impl TextRange {
+    pub fn new(start: TextSize, end: TextSize) -> TextRange {
+        assert!(start <= end, "invalid range: {:?}..{:?}", start, end);
+        TextRange { start, end }
+    }
+    pub fn at(offset: TextSize, len: TextSize) -> TextRange {
+        TextRange::new(offset, offset + len)
+    }
+    pub fn empty(offset: TextSize) -> TextRange {
+        TextRange::new(offset, offset)
+    }
+    pub fn up_to(end: TextSize) -> TextRange {
+        TextRange::new(TextSize::default(), end)
+    }
+
+    pub fn start(self) -> TextSize {
+        self.start
+    }
+    pub fn end(self) -> TextSize {
+        self.end
+    }
+    pub fn len(self) -> TextSize {
+        self.end - self.start
+    }
+    pub fn is_empty(self) -> bool {
+        self.start == self.end
+    }
+
+    /// Checks if `offset` is inside the range, excluding the end.
+    pub fn contains(self, offset: TextSize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+    /// Checks if `offset` is inside the range, including the end.
+    pub fn contains_inclusive(self, offset: TextSize) -> bool {
+        self.start <= offset && offset <= self.end
+    }
+    pub fn contains_range(self, other: TextRange) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+    /// The common part of two ranges, if any. Touching ranges intersect at
+    /// an empty range.
+    pub fn intersect(self, other: TextRange) -> Option<TextRange> {
+        let start = self.start.max(other.start);
+        let end = self.end.min(other.end);
+        if end < start {
+            return None;
+        }
+        Some(TextRange::new(start, end))
+    }
+    /// The smallest range containing both ranges.
+    pub fn cover(self, other: TextRange) -> TextRange {
+        let start = self.start.min(other.start);
+        let end = self.end.max(other.end);
+        TextRange::new(start, end)
+    }
+    pub fn cover_offset(self, offset: TextSize) -> TextRange {
+        self.cover(TextRange::empty(offset))
+    }
+    pub fn checked_sub(self, offset: TextSize) -> Option<TextRange> {
+        let start = self.start.checked_sub(offset)?;
+        let end = self.end.checked_sub(offset)?;
+        Some(TextRange::new(start, end))
+    }
+}
+
+impl fmt::Debug for TextRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+impl Add<TextSize> for TextRange {
+    type Output = TextRange;
+    fn add(self, offset: TextSize) -> TextRange {
+        TextRange::new(self.start + offset, self.end + offset)
+    }
+}
+
+impl Sub<TextSize> for TextRange {
+    type Output = TextRange;
+    fn sub(self, offset: TextSize) -> TextRange {
+        TextRange::new(self.start - offset, self.end - offset)
+    }
+}
+
+impl RangeBounds<TextSize> for TextRange {
+    fn start_bound(&self) -> Bound<&TextSize> {
+        Bound::Included(&self.start)
+    }
+    fn end_bound(&self) -> Bound<&TextSize> {
+        Bound::Excluded(&self.end)
+    }
+}
+
+impl Index<TextRange> for str {
+    type Output = str;
+    fn index(&self, index: TextRange) -> &str {
+        &self[usize::from(index.start)..usize::from(index.end)]
+    }
+}
@@ -0,0 +1,83 @@
+/// The result of looking up a token by offset: an offset can fall inside a
+/// single token, or on the boundary between two adjacent tokens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenAtOffset<T> {
+    None,
+    Single(T),
+    Between(T, T),
+}
+
+impl<T> TokenAtOffset<T> {
+    pub fn map<F: FnMut(T) -> U, U>(self, mut f: F) -> TokenAtOffset<U> {
+        match self {
+            TokenAtOffset::None => TokenAtOffset::None,
+            TokenAtOffset::Single(it) => TokenAtOffset::Single(f(it)),
+            TokenAtOffset::Between(l, r) => TokenAtOffset::Between(f(l), f(r)),
+        }
+    }
+
+    /// Convert to option, preferring the right token in case of a tie.
+    pub fn right_biased(self) -> Option<T> {
+        match self {
+            TokenAtOffset::None => None,
+            TokenAtOffset::Single(it) => Some(it),
+            TokenAtOffset::Between(_, r) => Some(r),
+        }
+    }
+
+    /// Convert to option, preferring the left token in case of a tie.
+    pub fn left_biased(self) -> Option<T> {
+        match self {
+            TokenAtOffset::None => None,
+            TokenAtOffset::Single(it) => Some(it),
+            TokenAtOffset::Between(l, _) => Some(l),
+        }
+    }
+}
+
+impl<T> Iterator for TokenAtOffset<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match std::mem::replace(self, TokenAtOffset::None) {
+            TokenAtOffset::None => None,
+            TokenAtOffset::Single(it) => Some(it),
+            TokenAtOffset::Between(l, r) => {
+                *self = TokenAtOffset::Single(r);
+                Some(l)
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            TokenAtOffset::None => (0, Some(0)),
+            TokenAtOffset::Single(_) => (1, Some(1)),
+            TokenAtOffset::Between(_, _) => (2, Some(2)),
+        }
+    }
+}
+
+impl<T> ExactSizeIterator for TokenAtOffset<T> {}
+
+/// An event of a preorder traversal.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum WalkEvent<T> {
+    Enter(T),
+    Leave(T),
+}
+
+impl<T> WalkEvent<T> {
+    pub fn map<F: FnOnce(T) -> U, U>(self, f: F) -> WalkEvent<U> {
+        match self {
+            WalkEvent::Enter(it) => WalkEvent::Enter(f(it)),
+            WalkEvent::Leave(it) => WalkEvent::Leave(f(it)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Next,
+    Prev,
+}
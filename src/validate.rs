@@ -0,0 +1,46 @@
+use std::{error::Error, fmt};
+
+/// Broken invariants, found by `PureTree::validate` or
+/// `SyntaxTree::validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    errors: Vec<String>,
+}
+
+impl ValidationError {
+    /// One message per broken invariant, prefixed with the index path from
+    /// the validated tree to the element where it was found.
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} broken invariant(s)", self.errors.len())?;
+        for error in &self.errors {
+            write!(f, "\n  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ValidationError {}
+
+#[derive(Default)]
+pub(crate) struct Report {
+    errors: Vec<String>,
+}
+
+impl Report {
+    pub(crate) fn error(&mut self, path: &[usize], message: impl fmt::Display) {
+        self.errors.push(format!("at {:?}: {}", path, message))
+    }
+    pub(crate) fn finish(self) -> Result<(), ValidationError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError { errors: self.errors })
+        }
+    }
+}
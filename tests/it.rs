@@ -1,7 +1,31 @@
 use mini_rowan::*;
 
-#[rustfmt::skip]
+fn range(start: u32, end: u32) -> TextRange {
+    TextRange::new(start.into(), end.into())
+}
+
+/// Deterministic pseudo-random numbers below `n`.
+fn random() -> impl FnMut(usize) -> usize {
+    let mut seed = 92u64;
+    move |n| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) as usize % n
+    }
+}
+
+fn pure_tree_child(tree: &PureTree, index: usize) -> PureTree {
+    match &tree.get_child(index).unwrap().kind {
+        PureChildKind::Tree(it) => it.clone(),
+        PureChildKind::Token(_) => panic!(),
+    }
+}
+
 fn make_tree() -> SyntaxTree {
+    make_pure_tree().into()
+}
+
+#[rustfmt::skip]
+fn make_pure_tree() -> PureTree {
     fn kw(kw: &'static str) -> PureToken {
         PureToken::new(kw, kw)
     }
@@ -34,7 +58,7 @@ fn make_tree() -> SyntaxTree {
                 )
             )
         ).into();
-    func.into()
+    func
 }
 
 #[test]
@@ -73,3 +97,1281 @@ fn smoke_test() {
         assert_eq!(param_list.prev_sibling().unwrap().kind(), "fun");
     }
 }
+
+#[test]
+fn text() {
+    let func = make_tree();
+    assert_eq!(func.text(), "pubfunT:Clone()T:Eq");
+    assert_eq!(func.to_string(), func.text().to_string());
+
+    let param_bound = func
+        .find_tree("generic-param-list")
+        .unwrap()
+        .find_tree("param-decl")
+        .unwrap()
+        .find_tree("param-bound")
+        .unwrap();
+    assert_eq!(param_bound.text(), ":Clone");
+
+    param_bound.detach();
+    assert_eq!(func.text(), "pubfunT()T:Eq");
+    assert_eq!(param_bound.text(), ":Clone");
+
+    let where_pred = func.find_tree("where-clause").unwrap().find_tree("where-pred").unwrap();
+    where_pred.insert_child(0, param_bound.into());
+    assert_eq!(where_pred.text(), ":CloneT:Eq");
+    assert_eq!(func.text(), "pubfunT():CloneT:Eq");
+
+    let fun_kw = func.find_token("fun").unwrap();
+    assert_eq!(fun_kw.to_string(), "fun");
+}
+
+#[test]
+fn syntax_text() {
+    let func = make_tree();
+    let text = func.text();
+    assert_eq!(text.len(), 19.into());
+    assert_eq!(text, "pubfunT:Clone()T:Eq");
+    assert_eq!(text.char_at(0.into()), Some('p'));
+    assert_eq!(text.char_at(7.into()), Some(':'));
+    assert_eq!(text.char_at(19.into()), None);
+    assert!(text.contains_char('C'));
+    assert!(!text.contains_char('x'));
+    assert_eq!(text.find_char(':'), Some(7.into()));
+    assert_eq!(text.find_char('x'), None);
+
+    let slice = text.slice(range(4, 10));
+    assert_eq!(slice, "unT:Cl");
+    assert_eq!(slice.char_at(3.into()), Some(':'));
+    assert_eq!(slice.find_char('C'), Some(4.into()));
+    assert_eq!(slice.slice(TextSize::from(1)..), "nT:Cl");
+    assert_eq!(slice.slice(..TextSize::from(0)), "");
+    assert!(slice.slice(..TextSize::from(0)).is_empty());
+
+    let mut chunks = Vec::new();
+    slice.for_each_chunk(|chunk| chunks.push(chunk.to_string()));
+    assert_eq!(chunks, vec!["un", "T", ":", "Cl"]);
+
+    assert_eq!(text.slice(range(13, 15)), func.find_tree("param-list").unwrap().text());
+
+    // The text is a snapshot, unaffected by later edits.
+    func.find_token("pub").unwrap().detach();
+    assert_eq!(text, "pubfunT:Clone()T:Eq");
+    assert_eq!(func.text(), "funT:Clone()T:Eq");
+
+    let param_list = func.find_tree("param-list").unwrap();
+    param_list.detach();
+    let where_pred = func.find_tree("where-clause").unwrap().find_tree("where-pred").unwrap();
+    where_pred.insert_child(2, param_list.into());
+    assert_eq!(func.text(), "funT:CloneT:Eq()");
+    assert_eq!(func.text().slice(TextSize::from(14)..), "()");
+}
+
+#[test]
+fn syntax_text_equality() {
+    fn text<'a>(tokens: impl IntoIterator<Item = &'a str>) -> SyntaxText {
+        let tree = tokens
+            .into_iter()
+            .fold(PureTree::new("array"), |tree, text| tree.push(PureToken::new("ident", text)));
+        SyntaxTree::<StrLanguage>::from(PureTree::from(tree)).text()
+    }
+
+    // Chunks end in the middle of different chars.
+    assert_eq!(text(["αβ", "γ"]), text(["α", "", "βγ"]));
+    assert_ne!(text(["ab", "c"]), text(["a", "bd"]));
+    assert_ne!(text(["ab"]), text(["ab", "c"]));
+    assert_eq!(text(["ab", "cd"]).slice(range(1, 3)), text(["xb", "cx"]).slice(range(1, 3)));
+
+    // Comparison walks both texts once, which keeps large texts fast.
+    let n = 20_000;
+    let lhs = text((0..n).map(|_| "ab"));
+    let rhs = text((0..n).flat_map(|_| ["a", "b"]));
+    assert_eq!(lhs, rhs);
+    let other = text((0..n).map(|i| if i + 1 == n { "ax" } else { "ab" }));
+    assert_ne!(lhs, other);
+    assert_eq!(lhs.slice(range(1, 2 * n - 1)), rhs.slice(range(1, 2 * n - 1)));
+}
+
+#[test]
+fn text_ranges() {
+    let func = make_tree();
+    assert_eq!(func.text_range(), range(0, 19));
+
+    let param_list = func.find_tree("param-list").unwrap();
+    assert_eq!(param_list.text_range(), range(13, 15));
+    let r_paren = param_list.find_token(")").unwrap();
+    assert_eq!(r_paren.text_range(), range(14, 15));
+    assert_eq!(SyntaxChild::from(r_paren).text_range(), range(14, 15));
+
+    let where_clause = func.find_tree("where-clause").unwrap();
+    assert_eq!(where_clause.text_range(), range(15, 19));
+    func.find_token("pub").unwrap().detach();
+    assert_eq!(where_clause.text_range(), range(12, 16));
+
+    let r = range(2, 5);
+    assert_eq!(r.len(), 3.into());
+    assert!(r.contains(2.into()));
+    assert!(!r.contains(5.into()));
+    assert!(r.contains_inclusive(5.into()));
+    assert_eq!(r.intersect(range(4, 10)), Some(range(4, 5)));
+    assert_eq!(r.intersect(range(5, 10)), Some(range(5, 5)));
+    assert_eq!(r.intersect(range(6, 10)), None);
+    assert_eq!(r.cover(range(7, 10)), range(2, 10));
+    assert_eq!(&"hello world"[r], "llo");
+}
+
+#[test]
+fn token_at_offset() {
+    let func = make_tree();
+    let at = |offset: u32| -> Vec<String> {
+        func.token_at_offset(offset.into()).map(|it| it.text().to_string()).collect()
+    };
+    assert_eq!(at(0), vec!["pub"]);
+    assert_eq!(at(1), vec!["pub"]);
+    assert_eq!(at(3), vec!["pub", "fun"]);
+    assert_eq!(at(7), vec!["T", ":"]);
+    assert_eq!(at(13), vec!["Clone", "("]);
+    assert_eq!(at(19), vec!["Eq"]);
+
+    let param_list = func.find_tree("param-list").unwrap();
+    let tokens = param_list.token_at_offset(14.into());
+    assert_eq!(tokens.clone().left_biased().unwrap().kind(), "(");
+    assert_eq!(tokens.right_biased().unwrap().kind(), ")");
+
+    let empty: SyntaxTree = PureTree::from(PureTree::new("empty")).into();
+    assert_eq!(empty.token_at_offset(0.into()), TokenAtOffset::None);
+}
+
+#[test]
+fn covering_element() {
+    let func = make_tree();
+    assert_eq!(func.covering_element(range(8, 10)).kind(), "ident");
+    assert_eq!(func.covering_element(range(7, 13)).kind(), "param-bound");
+    assert_eq!(func.covering_element(range(6, 13)).kind(), "param-decl");
+    assert_eq!(func.covering_element(range(5, 7)), SyntaxChild::Tree(func.clone()));
+    assert_eq!(func.covering_element(range(3, 3)).kind(), "pub");
+
+    // Handles are the same as the ones obtained by navigation.
+    let param_list = func.find_tree("param-list").unwrap();
+    assert_eq!(func.covering_element(range(13, 15)), SyntaxChild::Tree(param_list));
+}
+
+#[test]
+fn preorder() {
+    let func = make_tree();
+
+    let trees: Vec<_> = func.descendants().map(|it| it.kind()).collect();
+    assert_eq!(
+        trees,
+        vec![
+            "function-decl",
+            "generic-param-list",
+            "param-decl",
+            "param-bound",
+            "param-list",
+            "where-clause",
+            "where-pred",
+            "param-bound",
+        ]
+    );
+    let pure_trees: Vec<_> =
+        make_pure_tree().descendants().map(|it| StrLanguage::kind_from_raw(it.kind())).collect();
+    assert_eq!(pure_trees, trees);
+
+    let tokens: String = func
+        .descendants_with_tokens()
+        .filter_map(|it| match it {
+            SyntaxChild::Token(it) => Some(it.text().to_string()),
+            SyntaxChild::Tree(_) => None,
+        })
+        .collect();
+    assert_eq!(tokens, func.text().to_string());
+
+    let mut events = Vec::new();
+    let mut preorder = func.find_tree("where-clause").unwrap().preorder_with_tokens();
+    while let Some(event) = preorder.next() {
+        match &event {
+            WalkEvent::Enter(it) if it.kind() == "param-bound" => preorder.skip_subtree(),
+            _ => (),
+        }
+        events.push(event.map(|it| it.kind()));
+    }
+    assert_eq!(
+        events,
+        vec![
+            WalkEvent::Enter("where-clause"),
+            WalkEvent::Enter("where-pred"),
+            WalkEvent::Enter("ident"),
+            WalkEvent::Leave("ident"),
+            WalkEvent::Enter("param-bound"),
+            WalkEvent::Leave("param-bound"),
+            WalkEvent::Leave("where-pred"),
+            WalkEvent::Leave("where-clause"),
+        ]
+    );
+
+    let where_clause =
+        make_pure_tree().descendants().find(|it| it.kind() == RawKind::from("where-clause"));
+    let mut pure_events = Vec::new();
+    let mut preorder = where_clause.unwrap().preorder_with_tokens();
+    while let Some(event) = preorder.next() {
+        match &event {
+            WalkEvent::Enter(it) if it.kind() == RawKind::from("param-bound") => {
+                preorder.skip_subtree()
+            }
+            _ => (),
+        }
+        pure_events.push(event.map(|it| StrLanguage::kind_from_raw(it.kind())));
+    }
+    assert_eq!(pure_events, events);
+}
+
+#[test]
+fn navigation() {
+    let func = make_tree();
+    assert_eq!(func.children_count(), 5);
+    assert_eq!(func.last_child().unwrap().kind(), "where-clause");
+    assert_eq!(func.index(), 0);
+
+    let param_bound = func
+        .find_tree("where-clause")
+        .unwrap()
+        .find_tree("where-pred")
+        .unwrap()
+        .find_tree("param-bound")
+        .unwrap();
+    let ancestors: Vec<_> = param_bound.ancestors().map(|it| it.kind()).collect();
+    assert_eq!(ancestors, vec!["param-bound", "where-pred", "where-clause", "function-decl"]);
+    assert_eq!(param_bound.root(), func);
+    assert_eq!(param_bound.index(), 1);
+
+    let eq = param_bound.last_child().unwrap();
+    assert_eq!(eq.index(), 1);
+    assert_eq!(eq.ancestors().count(), 4);
+
+    let param_list = func.find_tree("param-list").unwrap();
+    assert_eq!(param_list.index(), 3);
+    let next: Vec<_> = param_list.siblings(Direction::Next).map(|it| it.kind()).collect();
+    assert_eq!(next, vec!["param-list", "where-clause"]);
+    let prev: Vec<_> = param_list.siblings(Direction::Prev).map(|it| it.kind()).collect();
+    assert_eq!(prev, vec!["param-list", "generic-param-list", "fun", "pub"]);
+
+    let fun_kw = func.find_token("fun").unwrap();
+    assert_eq!(fun_kw.index(), 1);
+    fun_kw.prev_sibling().unwrap().detach();
+    assert_eq!(fun_kw.index(), 0);
+    assert_eq!(param_list.index(), 2);
+    assert_eq!(fun_kw.siblings(Direction::Prev).count(), 1);
+
+    let empty: SyntaxTree = PureTree::from(PureTree::new("empty")).into();
+    assert_eq!(empty.children_count(), 0);
+    assert!(empty.last_child().is_none());
+    assert_eq!(empty.root(), empty);
+}
+
+#[test]
+fn token_stepping() {
+    let func = make_tree();
+    let first = func.first_token().unwrap();
+    assert_eq!(first.text(), "pub");
+    let forward: Vec<_> =
+        std::iter::successors(Some(first), |it| it.next_token()).map(|it| it.to_string()).collect();
+    assert_eq!(forward.concat(), func.text().to_string());
+
+    let last = func.last_token().unwrap();
+    assert_eq!(last.text(), "Eq");
+    let mut backward: Vec<_> =
+        std::iter::successors(Some(last), |it| it.prev_token()).map(|it| it.to_string()).collect();
+    backward.reverse();
+    assert_eq!(backward, forward);
+
+    // Handles obtained by stepping are the same as handles obtained by navigation.
+    let param_list = func.find_tree("param-list").unwrap();
+    let l_paren = param_list.first_token().unwrap();
+    let clone = l_paren.prev_token().unwrap();
+    assert_eq!(clone.text(), "Clone");
+    let param_bound = func
+        .find_tree("generic-param-list")
+        .unwrap()
+        .find_tree("param-decl")
+        .unwrap()
+        .find_tree("param-bound")
+        .unwrap();
+    assert_eq!(param_bound.last_child().unwrap(), SyntaxChild::Token(clone.clone()));
+    param_bound.detach();
+    assert_eq!(clone.parent().unwrap(), param_bound);
+    assert_eq!(l_paren.prev_token().unwrap().text(), "T");
+    assert!(clone.next_token().is_none());
+
+    // Empty trees are skipped.
+    let tree: SyntaxTree = PureTree::from(
+        PureTree::new("root")
+            .push(PureTree::new("empty"))
+            .push(PureToken::new("a", "a"))
+            .push(PureTree::new("empty"))
+            .push(PureTree::new("wrapper").push(PureTree::new("empty")))
+            .push(PureToken::new("b", "b"))
+            .push(PureTree::new("empty")),
+    )
+    .into();
+    assert_eq!(tree.first_token().unwrap().text(), "a");
+    assert_eq!(tree.last_token().unwrap().text(), "b");
+    assert_eq!(tree.first_token().unwrap().next_token().unwrap().text(), "b");
+    assert_eq!(tree.last_token().unwrap().prev_token().unwrap().text(), "a");
+    assert!(tree.find_tree("wrapper").unwrap().first_token().is_none());
+}
+
+#[test]
+fn typed_accessors() {
+    let tree: SyntaxTree = PureTree::from(
+        PureTree::new("root")
+            .push(PureToken::new("name", "x"))
+            .push(PureTree::new("name").push(PureToken::new("ident", "y"))),
+    )
+    .into();
+
+    let first = tree.first_child().unwrap();
+    assert_eq!(first.as_token().unwrap().text(), "x");
+    assert!(first.as_tree().is_none());
+    let last = tree.last_child().unwrap();
+    assert_eq!(last.as_tree().unwrap().text(), "y");
+    assert!(last.as_token().is_none());
+    assert!(last.clone().into_token().is_none());
+    assert_eq!(last.clone().into_tree().unwrap(), tree.find_tree("name").unwrap());
+
+    // A tree and a token may share a kind.
+    assert_eq!(tree.find_tree("name").unwrap().text(), "y");
+    assert_eq!(tree.find_token("name").unwrap().text(), "x");
+    assert!(tree.find_tree("ident").is_none());
+    assert!(tree.find_token("ident").is_none());
+}
+
+#[test]
+fn insert_child_attaches_handle() {
+    let func = make_tree();
+    let param_list = func.find_tree("param-list").unwrap();
+    param_list.detach();
+    assert!(param_list.parent().is_none());
+
+    let where_pred = func.find_tree("where-clause").unwrap().find_tree("where-pred").unwrap();
+    let ident = where_pred.first_child().unwrap();
+    where_pred.insert_child(1, param_list.clone().into());
+    assert_eq!(param_list.parent().unwrap(), where_pred);
+    assert_eq!(param_list.index(), 1);
+    assert_eq!(ident.next_sibling().unwrap(), SyntaxChild::Tree(param_list.clone()));
+    assert_eq!(param_list.offset(), 14.into());
+}
+
+#[test]
+fn replace_with() {
+    let func = make_tree();
+    let fun_kw = func.find_token("fun").unwrap();
+    let param_list = func.find_tree("param-list").unwrap();
+    let where_clause = func.find_tree("where-clause").unwrap();
+    let generic_param_list = func.find_tree("generic-param-list").unwrap();
+    assert_eq!(generic_param_list.index(), 2);
+
+    let replacement: SyntaxTree =
+        PureTree::from(PureTree::new("generic-param-list").push(PureToken::new("ident", "U")))
+            .into();
+    generic_param_list.replace_with(replacement.clone().into());
+    assert_eq!(func.text(), "pubfunU()T:Eq");
+
+    // Old handle becomes a standalone tree.
+    assert!(generic_param_list.parent().is_none());
+    assert_eq!(generic_param_list.text(), "T:Clone");
+    assert_eq!(generic_param_list.index(), 0);
+
+    // New handle is live, siblings keep their indices.
+    assert_eq!(replacement.parent().unwrap(), func);
+    assert_eq!(replacement.index(), 2);
+    assert_eq!(func.find_tree("generic-param-list").unwrap(), replacement);
+    assert_eq!(fun_kw.next_sibling().unwrap(), SyntaxChild::Tree(replacement.clone()));
+    assert_eq!(param_list.index(), 3);
+    assert_eq!(param_list.offset(), 7.into());
+    assert_eq!(where_clause.offset(), 9.into());
+
+    // Tokens can be replaced as well, by trees or by tokens.
+    let eq = where_clause.last_token().unwrap();
+    let fun = SyntaxChild::Token(fun_kw.clone());
+    fun.replace_with(SyntaxChild::Tree(generic_param_list.clone()));
+    assert_eq!(func.text(), "pubT:CloneU()T:Eq");
+    assert!(fun_kw.parent().is_none());
+    assert_eq!(fun_kw.text(), "fun");
+    assert_eq!(generic_param_list.index(), 1);
+    assert_eq!(eq.offset(), 15.into());
+}
+
+#[test]
+#[should_panic(expected = "can't replace a root")]
+fn replace_root() {
+    let func = make_tree();
+    let fun_kw = func.find_token("fun").unwrap();
+    fun_kw.detach();
+    func.replace_with(fun_kw.into());
+}
+
+#[test]
+#[should_panic(expected = "can't replace an element with its own root")]
+fn replace_with_own_root() {
+    let func = make_tree();
+    let param_list = func.find_tree("param-list").unwrap();
+    param_list.replace_with(func.into());
+}
+
+#[test]
+#[should_panic(expected = "can't insert the root of a tree into that tree")]
+fn insert_own_root() {
+    let func = make_tree();
+    let param_list = func.find_tree("param-list").unwrap();
+    param_list.insert_child(0, func.into());
+}
+
+#[test]
+fn splice_children() {
+    let mut pure = PureTree::new("list");
+    for text in ["a", "b", "c", "d", "e", "f"].iter() {
+        pure = pure.push(PureToken::new("letter", *text));
+    }
+    let list: SyntaxTree = PureTree::from(pure).into();
+    let children: Vec<_> = list.children().collect();
+
+    let new_children = [
+        SyntaxTree::from(PureTree::from(PureTree::new("x").push(PureToken::new("x", "xx")))),
+        SyntaxTree::from(PureTree::from(PureTree::new("y").push(PureToken::new("y", "yy")))),
+    ];
+    list.splice_children(1..4, new_children.iter().cloned().map(SyntaxChild::from));
+    assert_eq!(list.text(), "axxyyef");
+    assert_eq!(list.children_count(), 5);
+
+    assert_eq!(children[0].index(), 0);
+    for removed in &children[1..4] {
+        assert!(removed.parent().is_none());
+        assert_eq!(removed.index(), 0);
+    }
+    assert_eq!(children[4].index(), 3);
+    assert_eq!(children[4].offset(), 5.into());
+    assert_eq!(children[5].index(), 4);
+
+    assert_eq!(new_children[0].parent().unwrap(), list);
+    assert_eq!(new_children[1].index(), 2);
+    assert_eq!(new_children[1].offset(), 3.into());
+    assert_eq!(children[4].prev_sibling().unwrap(), SyntaxChild::Tree(new_children[1].clone()));
+
+    // Pure insertion and removal
+    list.splice_children(5..5, vec![children[2].clone()]);
+    list.splice_children(0..1, vec![]);
+    assert_eq!(list.text(), "xxyyefc");
+    assert_eq!(children[2].index(), 4);
+    assert_eq!(children[2].offset(), 6.into());
+    assert_eq!(new_children[0].index(), 0);
+}
+
+#[test]
+#[should_panic(expected = "children must be distinct detached elements")]
+fn splice_same_child_twice() {
+    let list: SyntaxTree = PureTree::from(PureTree::new("list")).into();
+    let child = SyntaxTree::from(PureTree::from(PureTree::new("x")));
+    list.splice_children(0..0, vec![child.clone().into(), child.into()]);
+}
+
+#[test]
+#[should_panic(expected = "can't splice the root of a tree into that tree")]
+fn splice_own_root() {
+    let func = make_tree();
+    let param_list = func.find_tree("param-list").unwrap();
+    param_list.splice_children(0..0, vec![func.into()]);
+}
+
+#[test]
+fn typed_language() {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    #[repr(u16)]
+    enum Kind {
+        BinExpr,
+        Literal,
+        Plus,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    enum Calc {}
+
+    impl Language for Calc {
+        type Kind = Kind;
+        fn kind_from_raw(raw: RawKind) -> Kind {
+            match raw.0 {
+                0 => Kind::BinExpr,
+                1 => Kind::Literal,
+                2 => Kind::Plus,
+                _ => unreachable!(),
+            }
+        }
+        fn kind_to_raw(kind: Kind) -> RawKind {
+            RawKind(kind as u16)
+        }
+    }
+
+    let raw = Calc::kind_to_raw;
+    let pure = PureTree::new(raw(Kind::BinExpr))
+        .push(PureToken::new(raw(Kind::Literal), "1"))
+        .push(PureToken::new(raw(Kind::Plus), "+"))
+        .push(PureToken::new(raw(Kind::Literal), "2"));
+    let expr: SyntaxTree<Calc> = PureTree::from(pure).into();
+
+    assert_eq!(expr.kind(), Kind::BinExpr);
+    let plus = expr.find_token(Kind::Plus).unwrap();
+    assert_eq!(plus.kind(), Kind::Plus);
+    assert_eq!(plus.next_sibling().unwrap().kind(), Kind::Literal);
+    assert!(expr.find_tree(Kind::Literal).is_none());
+    assert_eq!(format!("{:?}", plus), "\"+\": Plus");
+    assert_eq!(format!("{:?}", expr), "BinExpr");
+    assert_eq!(
+        format!("{:#?}", expr),
+        "BinExpr\n  \"1\": Literal\n  \"+\": Plus\n  \"2\": Literal\n"
+    );
+}
+
+#[test]
+fn unknown_raw_kind() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let tree = SyntaxTree::<StrLanguage>::from(PureTree::from(PureTree::new(RawKind(60000))));
+    assert_eq!(format!("{:?}", tree), "RawKind(60000)");
+    assert_eq!(format!("{:#?}", tree), "RawKind(60000)\n");
+    let err = catch_unwind(AssertUnwindSafe(|| tree.kind())).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<String>().unwrap(),
+        "RawKind(60000) was not interned by StrLanguage"
+    );
+
+    // The interner is still usable afterwards.
+    let tree = SyntaxTree::<StrLanguage>::from(PureTree::from(PureTree::new("list")));
+    assert_eq!(tree.kind(), "list");
+}
+
+#[test]
+fn pure_debug() {
+    // Kinds are printed raw, even those interned by `StrLanguage`.
+    let tree = PureTree::from(
+        PureTree::new(RawKind(0))
+            .push(PureToken::new(RawKind(1), "a"))
+            .push(PureTree::from(PureTree::new(RawKind(2)).push(PureToken::new(RawKind(1), "b")))),
+    );
+    assert_eq!(format!("{:?}", tree), "RawKind(0)");
+    let expected = r#"RawKind(0)
+  "a": RawKind(1)
+  RawKind(2)
+    "b": RawKind(1)
+"#;
+    assert_eq!(format!("{:#?}", tree), expected);
+    assert_eq!(
+        format!("{:?}", PureToken::new("ident", "x")),
+        format!("\"x\": {:?}", RawKind::from("ident"))
+    );
+}
+
+#[test]
+fn builder() {
+    let mut builder = PureTreeBuilder::new();
+    builder.start_node("function-decl");
+    builder.token("pub", "pub");
+    builder.token("fun", "fun");
+    builder.start_node("generic-param-list");
+    builder.start_node("param-decl");
+    builder.token("ident", "T");
+    builder.start_node("param-bound");
+    builder.token(":", ":");
+    builder.token("ident", "Clone");
+    builder.finish_node();
+    builder.finish_node();
+    builder.finish_node();
+    builder.start_node("param-list");
+    builder.token("(", "(");
+    builder.token(")", ")");
+    builder.finish_node();
+    builder.start_node("where-clause");
+    builder.start_node("where-pred");
+    builder.token("ident", "T");
+    builder.start_node("param-bound");
+    builder.token(":", ":");
+    builder.token("ident", "Eq");
+    builder.finish_node();
+    builder.finish_node();
+    builder.finish_node();
+    builder.finish_node();
+    let built: SyntaxTree = builder.finish().into();
+    assert_eq!(format!("{:#?}", built), format!("{:#?}", make_tree()));
+}
+
+#[test]
+fn builder_checkpoint() {
+    // 1 + 2 + 3, parsed as (1 + 2) + 3
+    let mut builder = PureTreeBuilder::new();
+    builder.start_node("file");
+    let checkpoint = builder.checkpoint();
+    builder.token("literal", "1");
+    for literal in ["2", "3"].iter() {
+        builder.start_node_at(checkpoint, "bin-expr");
+        builder.token("+", "+");
+        builder.token("literal", literal);
+        builder.finish_node();
+    }
+    builder.finish_node();
+    let file: SyntaxTree = builder.finish().into();
+    assert_eq!(file.text(), "1+2+3");
+
+    let outer = file.find_tree("bin-expr").unwrap();
+    assert_eq!(outer.text(), "1+2+3");
+    let inner = outer.find_tree("bin-expr").unwrap();
+    assert_eq!(inner.text(), "1+2");
+    assert_eq!(inner.index(), 0);
+}
+
+#[test]
+#[should_panic(expected = "unfinished trees")]
+fn builder_unbalanced_start() {
+    let mut builder = PureTreeBuilder::new();
+    builder.start_node("file");
+    builder.start_node("item");
+    builder.finish_node();
+    builder.finish();
+}
+
+#[test]
+#[should_panic(expected = "finish_node without start_node")]
+fn builder_unbalanced_finish() {
+    let mut builder = PureTreeBuilder::new();
+    builder.start_node("file");
+    builder.finish_node();
+    builder.finish_node();
+}
+
+#[test]
+fn node_cache() {
+    fn build(cache: &mut NodeCache) -> PureTree {
+        let mut builder = PureTreeBuilder::with_cache(cache);
+        builder.start_node("file");
+        for _ in 0..3 {
+            builder.start_node("call");
+            builder.token("ident", "f");
+            builder.start_node("arg-list");
+            builder.token("(", "(");
+            builder.token(")", ")");
+            builder.finish_node();
+            builder.finish_node();
+            builder.token("ws", " ");
+        }
+        builder.finish_node();
+        builder.finish()
+    }
+    let mut cache = NodeCache::new();
+    let file = build(&mut cache);
+    assert!(pure_tree_child(&file, 0).ptr_eq(&pure_tree_child(&file, 4)));
+    let stats = cache.stats();
+    assert_eq!(stats.token_misses, 4);
+    assert_eq!(stats.token_hits, 8);
+    assert_eq!(stats.tree_misses, 2);
+    assert_eq!(stats.tree_hits, 4);
+    assert!((stats.token_hit_rate() - 2.0 / 3.0).abs() < 1e-9);
+
+    // Elements are shared across files built with the same cache...
+    let other = build(&mut cache);
+    assert!(pure_tree_child(&file, 0).ptr_eq(&pure_tree_child(&other, 0)));
+    assert!(!file.ptr_eq(&other));
+
+    // ...and across edits.
+    let call = pure_tree_child(&file, 0);
+    let edited = call.remove_child(0);
+    assert!(!edited.ptr_eq(&call));
+    let restored = cache.insert_child(&edited, 0, PureToken::new("ident", "f").into());
+    assert!(restored.ptr_eq(&call));
+}
+
+#[test]
+fn structural_equality() {
+    use std::collections::HashMap;
+
+    let a = make_pure_tree();
+    let b = make_pure_tree();
+    assert!(!a.ptr_eq(&b));
+    assert_eq!(a, b);
+    assert_eq!(PureToken::new("ident", "x"), PureToken::new("ident", "x"));
+    assert_ne!(PureToken::new("ident", "x"), PureToken::new("ident", "y"));
+    assert_ne!(PureToken::new("ident", "x"), PureToken::new("name", "x"));
+
+    let mut memo = HashMap::new();
+    memo.insert(a.clone(), "analysis");
+    assert_eq!(memo.get(&b), Some(&"analysis"));
+
+    let edited = a.remove_child(0);
+    assert_ne!(edited, a);
+    assert!(!memo.contains_key(&edited));
+    let restored = edited.insert_child(0, PureToken::new("pub", "pub").into());
+    assert!(!restored.ptr_eq(&a));
+    assert_eq!(restored, a);
+    assert_eq!(memo.get(&restored), Some(&"analysis"));
+}
+
+#[test]
+fn diff_and_apply() {
+    let old = make_pure_tree();
+    let where_clause = pure_tree_child(&old, 4);
+    let where_pred = pure_tree_child(&where_clause, 0);
+    let new = old
+        .replace_child(4, where_clause.replace_child(0, where_pred.remove_child(1).into()).into())
+        .insert_child(5, PureToken::new(";", ";").into())
+        .replace_child(1, PureToken::new("fn", "fn").into());
+
+    let diff = diff(&old, &new);
+    assert_eq!(
+        diff.ops(),
+        &[
+            DiffOp::Replace { path: vec![], index: 1, child: PureToken::new("fn", "fn").into() },
+            DiffOp::Delete { path: vec![4, 0], index: 1 },
+            DiffOp::Insert { path: vec![], index: 5, child: PureToken::new(";", ";").into() },
+        ]
+    );
+    assert!(mini_rowan::diff(&old, &old).is_empty());
+
+    let live: SyntaxTree = old.into();
+    let param_list = live.find_tree("param-list").unwrap();
+    let where_pred = live.find_tree("where-clause").unwrap().find_tree("where-pred").unwrap();
+    let param_bound = where_pred.find_tree("param-bound").unwrap();
+    diff.apply(&live);
+
+    assert_eq!(live.text(), "pubfnT:Clone()T;");
+    assert_eq!(format!("{:#?}", live), format!("{:#?}", SyntaxTree::<StrLanguage>::from(new)));
+    assert_eq!(param_list.parent().unwrap(), live);
+    assert_eq!(where_pred.root(), live);
+    assert!(param_bound.parent().is_none());
+
+    // Shared children keep their handles when a sibling is inserted before
+    // them and another one is replaced after them.
+    let list = |text: &str| PureTree::from(PureTree::new("list").push(PureToken::new("x", text)));
+    let (b, c) = (list("b"), list("c"));
+    let old = PureTree::from(
+        PureTree::new("list")
+            .push(PureToken::new("x", "a"))
+            .push(b.clone())
+            .push(c.clone())
+            .push(PureToken::new("x", "d")),
+    );
+    let new =
+        old.insert_child(1, list("x").into()).replace_child(4, PureToken::new("x", "e").into());
+    let diff = mini_rowan::diff(&old, &new);
+    assert_eq!(
+        diff.ops(),
+        &[
+            DiffOp::Insert { path: vec![], index: 1, child: list("x").into() },
+            DiffOp::Replace { path: vec![], index: 4, child: PureToken::new("x", "e").into() },
+        ]
+    );
+    let live: SyntaxTree = old.into();
+    let mut children = live.children().skip(1).filter_map(SyntaxChild::into_tree);
+    let (b, c) = (children.next().unwrap(), children.next().unwrap());
+    diff.apply(&live);
+    assert_eq!(live.text(), "axbce");
+    assert_eq!((b.text().to_string(), b.index()), ("b".to_string(), 2));
+    assert_eq!((c.text().to_string(), c.index()), ("c".to_string(), 3));
+}
+
+#[test]
+fn mutation_recorder() {
+    fn apply(text: &mut String, edits: &[TextEdit]) {
+        for edit in edits.iter().rev() {
+            let range = usize::from(edit.range.start())..usize::from(edit.range.end());
+            text.replace_range(range, &edit.new_text);
+        }
+    }
+
+    let func = make_tree();
+    let mut text = func.text().to_string();
+    let recorder = MutationRecorder::new(&func);
+
+    // Adjacent insertions are merged into a single edit.
+    let param_list = func.find_tree("param-list").unwrap();
+    param_list.insert_child(1, SyntaxChild::Token(PureToken::new("ident", "x").into()));
+    param_list.insert_child(2, SyntaxChild::Token(PureToken::new(":", ":").into()));
+    param_list.insert_child(3, SyntaxChild::Token(PureToken::new("ident", "T").into()));
+    // Later edits are still reported in original coordinates.
+    func.find_tree("where-clause").unwrap().detach();
+    // Changes inside an earlier edit are folded into it.
+    param_list
+        .first_child()
+        .unwrap()
+        .next_sibling()
+        .unwrap()
+        .replace_with(SyntaxChild::Token(PureToken::new("ident", "y").into()));
+    func.first_token().unwrap().detach();
+    assert_eq!(func.text(), "funT:Clone(y:T)");
+
+    let edits = recorder.drain();
+    assert_eq!(
+        edits,
+        vec![
+            TextEdit { range: range(0, 3), new_text: String::new() },
+            TextEdit { range: range(14, 14), new_text: "y:T".to_string() },
+            TextEdit { range: range(15, 19), new_text: String::new() },
+        ]
+    );
+    apply(&mut text, &edits);
+    assert_eq!(func.text(), text);
+
+    // Recorders on subtrees report edits relative to the subtree.
+    let nested = MutationRecorder::new(&param_list);
+    param_list.last_child().unwrap().detach();
+    assert_eq!(recorder.drain(), vec![TextEdit { range: range(14, 15), new_text: String::new() }]);
+    assert_eq!(nested.drain(), vec![TextEdit { range: range(4, 5), new_text: String::new() }]);
+
+    // Undoing a change cancels the edit out.
+    let ws = SyntaxToken::from(PureToken::new("ws", " "));
+    func.insert_child(0, ws.clone().into());
+    ws.detach();
+    assert_eq!(recorder.drain(), vec![]);
+}
+
+#[test]
+fn history() {
+    let func = make_tree();
+    let history = History::new(&func);
+    assert!(!history.can_undo());
+
+    let param_list = func.find_tree("param-list").unwrap();
+    let where_clause = func.find_tree("where-clause").unwrap();
+    let bound = where_clause.find_tree("where-pred").unwrap().find_tree("param-bound").unwrap();
+
+    bound.detach();
+    history.set_label("remove bound");
+    let x = SyntaxToken::from(PureToken::new("ident", "x"));
+    param_list.insert_child(1, x.clone().into());
+    assert_eq!(func.text(), "pubfunT:Clone(x)T");
+
+    assert!(history.undo());
+    assert_eq!(func.text(), "pubfunT:Clone()T");
+    assert_eq!(history.label().as_deref(), Some("remove bound"));
+    // The inserted token no longer exists, other handles stay live.
+    assert!(x.parent().is_none());
+    assert_eq!(param_list.parent().unwrap(), func);
+    assert_eq!(param_list.text(), "()");
+
+    assert!(history.undo());
+    assert!(!history.undo());
+    assert_eq!(func.text(), "pubfunT:Clone()T:Eq");
+    assert_eq!(where_clause.text(), "T:Eq");
+    // Detached handles are not reattached.
+    assert!(bound.parent().is_none());
+
+    assert!(history.redo());
+    assert!(history.redo());
+    assert!(!history.redo());
+    assert_eq!(func.text(), "pubfunT:Clone(x)T");
+    assert_eq!(
+        format!("{:#?}", param_list),
+        format!("{:#?}", func.find_tree("param-list").unwrap())
+    );
+
+    // A new change discards the redo stack.
+    assert!(history.undo());
+    param_list.detach();
+    assert!(!history.can_redo());
+    assert_eq!(func.text(), "pubfunT:CloneT");
+    assert!(history.undo());
+    assert_eq!(func.text(), "pubfunT:Clone()T");
+
+    // Recorders on subtrees see the changes made by undo.
+    let param_list = func.find_tree("param-list").unwrap();
+    param_list.insert_child(1, SyntaxChild::Token(PureToken::new("ident", "y").into()));
+    let recorder = MutationRecorder::new(&param_list);
+    assert!(history.undo());
+    assert_eq!(param_list.text(), "()");
+    assert_eq!(recorder.drain(), vec![TextEdit { range: range(1, 2), new_text: String::new() }]);
+}
+
+#[test]
+fn snapshot() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let func = make_tree();
+    let snapshot = func.snapshot();
+    assert_send_sync(&snapshot);
+    assert_eq!(snapshot, make_pure_tree());
+
+    let analysis = std::thread::spawn(move || snapshot.descendants().count());
+    func.find_tree("where-clause").unwrap().detach();
+    assert_eq!(analysis.join().unwrap(), 8);
+    assert_eq!(func.snapshot().descendants().count(), 5);
+
+    let token = func.first_token().unwrap();
+    assert_eq!(token.snapshot(), PureToken::new("pub", "pub"));
+    assert_eq!(SyntaxChild::Token(token).snapshot(), PureToken::new("pub", "pub").into());
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn sync_tree() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<SyntaxTree>();
+    assert_send_sync::<SyntaxToken>();
+    assert_send_sync::<SyntaxChild>();
+
+    let func = make_tree();
+    let param_list = func.find_tree("param-list").unwrap();
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let param_list = param_list.clone();
+            std::thread::spawn(move || {
+                for _ in 0..100 {
+                    let x = SyntaxToken::from(PureToken::new("ident", "x"));
+                    param_list.insert_child(1, x.clone().into());
+                    assert_eq!(x.parent().unwrap(), param_list);
+                    assert_eq!(param_list.first_token().unwrap().text(), "(");
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(param_list.children_count(), 402);
+    assert_eq!(func.text_len(), TextSize::from(19 + 400));
+
+    // Elements moving between trees used on different threads.
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let param_list = param_list.clone();
+            std::thread::spawn(move || {
+                let own: SyntaxTree = PureTree::from(PureTree::new("list")).into();
+                let x = SyntaxToken::from(PureToken::new("ident", "y"));
+                for _ in 0..100 {
+                    param_list.insert_child(1, x.clone().into());
+                    assert_eq!(x.parent().unwrap(), param_list);
+                    x.detach();
+                    own.insert_child(0, x.clone().into());
+                    assert_eq!(x.offset(), 0.into());
+                    x.detach();
+                }
+                assert_eq!(own.validate(), Ok(()));
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(param_list.children_count(), 402);
+    assert_eq!(func.validate(), Ok(()));
+
+    // Parents acquired while their last live child is detached stay attached.
+    for _ in 0..200 {
+        let inner = PureTree::new("inner").push(PureToken::new("ident", "z"));
+        let inner: SyntaxTree = PureTree::from(inner).into();
+        param_list.insert_child(1, inner.clone().into());
+        let z = inner.first_token().unwrap();
+        drop(inner);
+        std::thread::scope(|scope| {
+            scope.spawn(|| z.detach());
+            while let Some(parent) = z.parent() {
+                assert_eq!(parent.parent().unwrap(), param_list);
+            }
+        });
+        param_list.children().nth(1).unwrap().detach();
+    }
+    assert_eq!(param_list.children_count(), 402);
+    assert_eq!(func.validate(), Ok(()));
+
+    // Handles created on different threads share identity.
+    let where_clause = func.find_tree("where-clause").unwrap();
+    let other = {
+        let func = func.clone();
+        std::thread::spawn(move || func.find_tree("where-clause").unwrap()).join().unwrap()
+    };
+    assert_eq!(other, where_clause);
+    other.detach();
+    assert!(where_clause.parent().is_none());
+}
+
+#[test]
+fn pure_cursor() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<PureCursor>();
+
+    let func = make_tree();
+    let root = PureCursor::new(make_pure_tree());
+    let kind = |cursor: &PureCursor| StrLanguage::kind_from_raw(cursor.kind());
+
+    let children: Vec<_> = root.children().map(|it| kind(&it)).collect();
+    assert_eq!(children, ["pub", "fun", "generic-param-list", "param-list", "where-clause"]);
+    let param_list = root.get_child(3).unwrap();
+    assert_eq!(param_list.text_range(), func.find_tree("param-list").unwrap().text_range());
+    assert_eq!(param_list.index(), 3);
+    assert_eq!(param_list.parent().unwrap(), root);
+    assert_eq!(kind(&param_list.prev_sibling().unwrap()), "generic-param-list");
+    assert_eq!(param_list.siblings(Direction::Next).count(), 2);
+    assert_eq!(param_list.ancestors().count(), 1);
+
+    let lparen = param_list.first_token().unwrap();
+    assert_eq!(lparen.as_token().unwrap().text(), "(");
+    assert_eq!(lparen.prev_token().unwrap().as_token().unwrap().text(), "Clone");
+    assert_eq!(param_list.next_token().unwrap().as_token().unwrap().text(), "T");
+    assert_eq!(root.last_token().unwrap().offset(), 17.into());
+    assert!(root.last_token().unwrap().next_token().is_none());
+
+    // Traversals agree with the mutable tree.
+    let pure_kinds: Vec<_> =
+        root.descendants_with_tokens().map(|it| (kind(&it), it.text_range())).collect();
+    let kinds: Vec<_> =
+        func.descendants_with_tokens().map(|it| (it.kind(), it.text_range())).collect();
+    assert_eq!(pure_kinds, kinds);
+    let mut preorder = root.preorder_with_tokens();
+    let mut entered = Vec::new();
+    while let Some(event) = preorder.next() {
+        if let WalkEvent::Enter(it) = event {
+            if it.as_tree().is_some() && it != root {
+                preorder.skip_subtree();
+            }
+            entered.push(kind(&it));
+        }
+    }
+    assert_eq!(
+        entered,
+        ["function-decl", "pub", "fun", "generic-param-list", "param-list", "where-clause"]
+    );
+
+    let at_offset = |offset: u32| {
+        root.token_at_offset(offset.into())
+            .map(|it| it.as_token().unwrap().text().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(at_offset(14), ["(", ")"]);
+    assert_eq!(at_offset(9), ["Clone"]);
+    let covering = root.covering_element(range(7, 13));
+    assert_eq!(kind(&covering), "param-bound");
+    assert_eq!(covering, root.get_child(2).unwrap().get_child(0).unwrap().get_child(1).unwrap());
+}
+
+#[test]
+fn child_lookup_by_offset() {
+    let mut module = PureTree::new("module");
+    for i in 0..10_000 {
+        module =
+            module.push(PureTree::new("item").push(PureToken::new("ident", format!("i{:04}", i))));
+        module = module.push(PureToken::new("empty", ""));
+    }
+    let module: PureTree = module.into();
+
+    let (idx, child) = module.child_at_offset(12_345.into()).unwrap();
+    assert_eq!((idx, child.offset), (4_938, 12_345.into()));
+    assert_eq!(module.child_at_offset(12_350.into()).unwrap().0, 4_940);
+    assert!(module.child_at_offset(50_000.into()).is_none());
+
+    let indices: Vec<usize> =
+        module.children_in_range(range(12_344, 12_351)).map(|(idx, _)| idx).collect();
+    assert_eq!(indices, [4_936, 4_937, 4_938, 4_939, 4_940]);
+    assert_eq!(module.children_in_range(range(12_345, 12_345)).count(), 0);
+    assert_eq!(module.children_in_range(range(12_346, 12_346)).count(), 1);
+
+    let func = make_tree();
+    let param_list = func.find_tree("param-list").unwrap();
+    assert_eq!(func.child_at_offset(14.into()).unwrap(), SyntaxChild::Tree(param_list.clone()));
+    assert_eq!(param_list.child_at_offset(14.into()).unwrap().to_string(), ")");
+    assert!(param_list.child_at_offset(3.into()).is_none());
+    let kinds: Vec<&str> = func.children_in_range(range(5, 14)).map(|it| it.kind()).collect();
+    assert_eq!(kinds, ["fun", "generic-param-list", "param-list"]);
+}
+
+#[test]
+fn wide_tree_edits() {
+    fn item(text: &str) -> PureChildKind {
+        PureToken::new("ident", text).into()
+    }
+    let mut model: Vec<String> = (0..100_000).map(|i| format!("{};", i)).collect();
+    let original: PureTree =
+        model.iter().fold(PureTree::new("array"), |tree, text| tree.push(item(text))).into();
+
+    let mut random = random();
+    let mut tree = original.clone();
+    for i in 0..3_000 {
+        let text = format!("x{}", i);
+        match random(3) {
+            0 => {
+                let idx = random(model.len() + 1);
+                tree = tree.insert_child(idx, item(&text));
+                model.insert(idx, text);
+            }
+            1 => {
+                let idx = random(model.len());
+                tree = tree.remove_child(idx);
+                model.remove(idx);
+            }
+            _ => {
+                let idx = random(model.len());
+                tree = tree.replace_child(idx, item(&text));
+                model[idx] = text;
+            }
+        }
+    }
+
+    assert_eq!(tree.children_count(), model.len());
+    assert_eq!(tree.text(), model.concat());
+    let mut offset = TextSize::default();
+    for (idx, (child, text)) in tree.children().zip(&model).enumerate() {
+        assert_eq!(child.offset, offset);
+        assert_eq!(child.kind.to_string(), *text);
+        if idx % 1_000 == 0 {
+            assert_eq!(tree.get_child(idx).unwrap(), child);
+            assert_eq!(tree.child_at_offset(offset).unwrap(), (idx, child));
+        }
+        offset += TextSize::of(text);
+    }
+
+    // Structure doesn't depend on the edit history, and old versions are
+    // unaffected by edits.
+    let rebuilt: PureTree =
+        model.iter().fold(PureTree::new("array"), |tree, text| tree.push(item(text))).into();
+    assert_eq!(tree, rebuilt);
+    assert_eq!(original.children_count(), 100_000);
+    assert_eq!(original.get_child(50_000).unwrap().kind.to_string(), "50000;");
+
+    // Wide trees shrink back to small ones.
+    let small = tree.splice_children(3..tree.children_count(), None);
+    assert_eq!(small.text(), model[..3].concat());
+    assert_eq!(
+        small,
+        PureTree::from(
+            PureTree::new("array")
+                .push(item(&model[0]))
+                .push(item(&model[1]))
+                .push(item(&model[2]))
+        )
+    );
+}
+
+#[test]
+fn many_live_children() {
+    let pure: PureTree = (0..20_000)
+        .fold(PureTree::new("array"), |it, i| it.push(PureToken::new("int", i.to_string())))
+        .into();
+    let tree = SyntaxTree::<StrLanguage>::from(pure);
+    let mut handles: Vec<SyntaxChild> = tree.children().collect();
+
+    let mut random = random();
+    for i in 0..20_000 {
+        let index = random(handles.len() + 1);
+        if i % 3 == 0 && index < handles.len() {
+            handles.remove(index).detach();
+        } else {
+            let token = SyntaxChild::Token(PureToken::new("int", "0").into());
+            tree.insert_child(index, token.clone());
+            handles.insert(index, token);
+        }
+    }
+
+    assert_eq!(tree.children_count(), handles.len());
+    for (index, handle) in handles.iter().enumerate() {
+        assert_eq!(handle.index(), index);
+    }
+    assert_eq!(tree.children().collect::<Vec<_>>(), handles);
+    let removed: Vec<SyntaxChild> = handles.drain(100..handles.len() - 100).collect();
+    tree.splice_children(100..tree.children_count() - 100, None);
+    assert!(removed.iter().all(|it| it.parent().is_none() && it.index() == 0));
+    assert_eq!(tree.children().collect::<Vec<_>>(), handles);
+}
+
+#[test]
+fn deep_tree() {
+    // `1+1+...+1`, parsed as a left-leaning chain of binary expressions.
+    fn chain(depth: usize) -> PureTree {
+        let (plus, one) = (PureToken::new("+", "+"), PureToken::new("int", "1"));
+        let mut tree: PureTree = PureTree::new("lit").push(one.clone()).into();
+        for _ in 1..depth {
+            tree = PureTree::new("bin").push(tree).push(plus.clone()).push(one.clone()).into();
+        }
+        tree
+    }
+    let depth = 1_000_000;
+    let pure = chain(depth);
+    let text = pure.to_string();
+    assert_eq!(text.len(), 2 * depth - 1);
+
+    let other = chain(depth);
+    assert_eq!(pure, other);
+    let hash = |tree: &PureTree| {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        tree.hash(&mut hasher);
+        hasher.finish()
+    };
+    assert_eq!(hash(&pure), hash(&other));
+    drop(other);
+
+    let cursor = PureCursor::new(pure.clone()).first_token().unwrap();
+    assert_eq!(cursor.ancestors().count(), depth);
+    assert_eq!(PureCursor::new(pure.clone()).first_token(), Some(cursor));
+
+    let tree = SyntaxTree::<StrLanguage>::from(pure.clone());
+    let first = tree.first_token().unwrap();
+    assert_eq!(first.offset(), 0.into());
+    let last = tree.last_token().unwrap();
+    assert_eq!(last.text_range(), range(2 * depth as u32 - 2, 2 * depth as u32 - 1));
+    assert_eq!(tree.text(), text.as_str());
+
+    first.replace_with(SyntaxChild::Token(PureToken::new("int", "2").into()));
+    let edited = tree.snapshot();
+    assert!(edited.to_string().starts_with("2+1+1"));
+    let changes = diff(&pure, &edited);
+    assert_eq!(changes.ops().len(), 1);
+    assert_eq!(
+        changes.ops()[0],
+        DiffOp::Replace {
+            path: vec![0; depth - 1],
+            index: 0,
+            child: PureToken::new("int", "2").into(),
+        }
+    );
+}
+
+#[test]
+fn validate() {
+    let pure = make_pure_tree();
+    assert_eq!(pure.validate(), Ok(()));
+
+    let tree = make_tree();
+    let history = History::new(&tree);
+    let where_clause = tree.last_child().unwrap().into_tree().unwrap();
+    let bound = where_clause.first_child().unwrap().into_tree().unwrap().last_child().unwrap();
+    let fun_kw = tree.children().nth(1).unwrap();
+    assert_eq!(tree.validate(), Ok(()));
+
+    tree.first_child().unwrap().detach();
+    bound.replace_with(SyntaxChild::Token(PureToken::new("ident", "U").into()));
+    tree.insert_child(0, SyntaxChild::Token(PureToken::new("pub", "pub").into()));
+    tree.splice_children(2..4, vec![SyntaxChild::Token(PureToken::new(";", ";").into())]);
+    assert_eq!(fun_kw.index(), 1);
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(where_clause.validate(), Ok(()));
+    assert_eq!(tree.snapshot().validate(), Ok(()));
+
+    while history.undo() {
+        assert_eq!(tree.validate(), Ok(()));
+    }
+    assert_eq!(tree.snapshot(), pure);
+
+    // Wide trees, with live handles to many of the children.
+    let wide = SyntaxTree::<StrLanguage>::from(PureTree::from(
+        (0..1_000)
+            .fold(PureTree::new("array"), |it, i| it.push(PureToken::new("int", i.to_string()))),
+    ));
+    let handles: Vec<SyntaxChild> = wide.children().step_by(3).collect();
+    for (i, handle) in handles.iter().enumerate().rev() {
+        if i % 2 == 0 {
+            handle.detach();
+        } else {
+            wide.insert_child(
+                handle.index(),
+                SyntaxChild::Token(PureToken::new("int", "0").into()),
+            );
+        }
+    }
+    assert_eq!(wide.validate(), Ok(()));
+    wide.splice_children(10..wide.children_count(), None);
+    assert_eq!(wide.validate(), Ok(()));
+}